@@ -0,0 +1,224 @@
+extern crate sdl2;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
+use sdl2::surface::Surface;
+use sdl2::video::{Window, WindowContext};
+
+use std::collections::HashMap;
+
+// Side of a square texture page
+const PAGE_SIZE: u32 = 1024;
+// Pages kept before the least recently used one is emptied for new glyphs
+const MAX_PAGES: usize = 4;
+// Space left around each glyph so scaled or filtered copies don't bleed into neighbours
+const PADDING: u32 = 1;
+
+// A row of glyphs of at most `height` pixels, filled left to right
+struct Shelf {
+    y: u32,
+    height: u32,
+    x: u32,
+}
+
+struct Page<'a> {
+    texture: Texture<'a>,
+    width: u32,
+    height: u32,
+    shelves: Vec<Shelf>,
+    // Frame the page was last drawn from
+    last_used: u64,
+}
+
+// Where a glyph lives in the atlas; glyphs that render to nothing have no page
+#[derive(Debug, Clone, Copy)]
+pub struct Glyph {
+    page: Option<usize>,
+    x: i32,
+    y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+impl Glyph {
+    // Takes up space without drawing anything, for glyphs the atlas has no room for
+    pub fn blank(width: u32, height: u32) -> Glyph {
+        Glyph { page: None, x: 0, y: 0, width: width, height: height }
+    }
+}
+
+// A glyph to draw at (x, y) once the line it belongs to is complete
+pub struct Quad {
+    glyph: Glyph,
+    x: i32,
+    y: i32,
+    color: Color,
+}
+
+// Rendered glyphs packed into a few large textures, keyed by font style bits and grapheme, so a
+// screen of text is drawn from a handful of textures instead of one per character
+pub struct GlyphAtlas<'a> {
+    pages: Vec<Page<'a>>,
+    glyphs: HashMap<(i32, String), Glyph>,
+    frame: u64,
+
+    // Gives every glyph a texture of its own and draws them in order, the way text was drawn
+    // before the atlas, for `--bench --per-glyph` to compare against
+    pub per_glyph: bool,
+
+    // Counters for `--bench`
+    pub evictions: usize,
+    // Times a copy used another texture or tint than the one before, which breaks the
+    // renderer's batch
+    pub binds: usize,
+}
+impl<'a> GlyphAtlas<'a> {
+    pub fn new() -> GlyphAtlas<'a> {
+        GlyphAtlas { pages: Vec::new(), glyphs: HashMap::new(), frame: 0, per_glyph: false, evictions: 0, binds: 0 }
+    }
+
+    // Forgets every glyph, needed whenever the fonts change
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.glyphs.clear();
+    }
+
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn get_page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn get_glyph_count(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    pub fn get(&mut self, style: i32, character: &str) -> Option<Glyph> {
+        let glyph = *self.glyphs.get(&(style, character.to_owned()))?;
+        match glyph.page {
+            Some(page) => self.pages[page].last_used = self.frame,
+            None => (),
+        }
+        Some(glyph)
+    }
+
+    fn find_space(page: &mut Page, width: u32, height: u32) -> Option<(u32, u32)> {
+        for shelf in page.shelves.iter_mut() {
+            if height <= shelf.height && shelf.x + width <= page.width {
+                let x = shelf.x;
+                shelf.x += width;
+                return Some((x, shelf.y));
+            }
+        }
+
+        let y = page.shelves.last().map_or(0, |shelf| shelf.y + shelf.height);
+        if y + height <= page.height && width <= page.width {
+            page.shelves.push(Shelf { y: y, height: height, x: width });
+            return Some((0, y));
+        }
+        None
+    }
+
+    // Empties the page drawn from longest ago, never one already used this frame since glyphs
+    // queued for the current line may still point into it
+    fn evict(&mut self) -> Option<usize> {
+        let frame = self.frame;
+        let (index, _) = self.pages.iter().enumerate().filter(|(_, page)| page.last_used < frame).min_by_key(|(_, page)| page.last_used)?;
+
+        self.pages[index].shelves.clear();
+        self.glyphs.retain(|_, glyph| glyph.page != Some(index));
+        self.evictions += 1;
+        Some(index)
+    }
+
+    fn allocate(&mut self, width: u32, height: u32, texture_creator: &'a TextureCreator<WindowContext>) -> Result<(usize, u32, u32), String> {
+        if !self.per_glyph {
+            for (i, page) in self.pages.iter_mut().enumerate() {
+                match Self::find_space(page, width, height) {
+                    Some((x, y)) => return Ok((i, x, y)),
+                    None => (),
+                }
+            }
+        }
+
+        // Never more than MAX_PAGES, a glyph that fits nowhere this frame is left out until the next
+        let index = if self.pages.len() >= MAX_PAGES && !self.per_glyph {
+            self.evict().ok_or("Glyph atlas is full, some characters were not drawn".to_owned())?
+        }
+        else {
+            let (page_width, page_height) = if self.per_glyph { (width, height) } else { (PAGE_SIZE, PAGE_SIZE) };
+            let mut texture = texture_creator.create_texture_static(PixelFormatEnum::ARGB8888, page_width, page_height).map_err(|e| e.to_string())?;
+            texture.set_blend_mode(BlendMode::Blend);
+            self.pages.push(Page { texture: texture, width: page_width, height: page_height, shelves: Vec::new(), last_used: self.frame });
+            self.pages.len() - 1
+        };
+
+        match Self::find_space(&mut self.pages[index], width, height) {
+            Some((x, y)) => Ok((index, x, y)),
+            None => Err(format!["Glyph of {}x{} doesn't fit in a {}x{} atlas page", width, height, self.pages[index].width, self.pages[index].height]),
+        }
+    }
+
+    // Copies a glyph rendered in white into the atlas
+    pub fn insert(&mut self, style: i32, character: &str, surface: Option<Surface>, texture_creator: &'a TextureCreator<WindowContext>) -> Result<Glyph, String> {
+        let glyph = match surface {
+            Some(mut surface) => {
+                let (width, height) = (surface.width(), surface.height());
+                let (page, x, y) = self.allocate(width + PADDING, height + PADDING, texture_creator)?;
+
+                // Converted by blitting, without blending so the alpha is copied as it is
+                let mut converted = Surface::new(width, height, PixelFormatEnum::ARGB8888)?;
+                surface.set_blend_mode(BlendMode::None)?;
+                surface.blit(None, &mut converted, None)?;
+
+                let pixels = converted.without_lock().ok_or("Glyph surface needs locking".to_owned())?;
+                self.pages[page].texture.update(Rect::new(x as i32, y as i32, width, height), pixels, converted.pitch() as usize).map_err(|e| e.to_string())?;
+                self.pages[page].last_used = self.frame;
+
+                Glyph { page: Some(page), x: x as i32, y: y as i32, width: width, height: height }
+            },
+            None => Glyph { page: None, x: 0, y: 0, width: 0, height: 0 },
+        };
+
+        self.glyphs.insert((style, character.to_owned()), glyph);
+        Ok(glyph)
+    }
+
+    pub fn queue(batch: &mut Vec<Quad>, glyph: Glyph, x: i32, y: i32, color: Color) {
+        if glyph.page.is_some() {
+            batch.push(Quad { glyph: glyph, x: x, y: y, color: color });
+        }
+    }
+
+    // Draws the queued glyphs grouped by page and color, so each texture is bound and tinted
+    // once per group and the renderer can merge the copies
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, batch: &mut Vec<Quad>) {
+        if !self.per_glyph {
+            batch.sort_by_key(|quad| (quad.glyph.page, quad.color.rgb()));
+        }
+
+        let mut current = None;
+        for quad in batch.iter() {
+            let page = match quad.glyph.page {
+                Some(page) => page,
+                None => continue,
+            };
+            let texture = &mut self.pages[page].texture;
+
+            if current != Some((page, quad.color.rgb())) || self.per_glyph {
+                let (r, g, b) = quad.color.rgb();
+                texture.set_color_mod(r, g, b);
+                current = Some((page, quad.color.rgb()));
+                self.binds += 1;
+            }
+
+            let glyph = &quad.glyph;
+            let src = Rect::new(glyph.x, glyph.y, glyph.width, glyph.height);
+            let dst = Rect::new(quad.x, quad.y, glyph.width, glyph.height);
+            canvas.copy(texture, src, dst).unwrap();
+        }
+        batch.clear();
+    }
+}
@@ -0,0 +1,57 @@
+use ::atlas;
+
+use std::time::{Duration, Instant};
+
+// Lines scrolled between two frames
+pub const SCROLL_LINES: usize = 3;
+
+// Frame timings for `aurum --bench <file> [--software] [--per-glyph]`, which scrolls through a
+// file as fast as it can draw
+pub struct Bench {
+    frame_start: Instant,
+    frame_times: Vec<Duration>,
+    // Texture or tint changes per frame
+    frame_binds: Vec<usize>,
+}
+impl Bench {
+    pub fn new() -> Bench {
+        Bench { frame_start: Instant::now(), frame_times: Vec::new(), frame_binds: Vec::new() }
+    }
+
+    pub fn start_frame(&mut self) {
+        self.frame_start = Instant::now();
+    }
+
+    pub fn end_frame(&mut self, binds: usize) {
+        self.frame_times.push(self.frame_start.elapsed());
+        self.frame_binds.push(binds);
+    }
+
+    fn to_ms(d: &Duration) -> f64 {
+        d.as_secs() as f64*1000.0 + d.subsec_nanos() as f64/1_000_000.0
+    }
+
+    pub fn report(&self, atlas: &atlas::GlyphAtlas) {
+        if self.frame_times.len() == 0 {
+            println!["No frames drawn"];
+            return;
+        }
+
+        let mut times: Vec<f64> = self.frame_times.iter().map(Self::to_ms).collect();
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let total: f64 = times.iter().sum();
+        let percentile = |p: usize| times[(times.len() - 1)*p/100];
+
+        println!["frames: {}", times.len()];
+        println!["total:  {:.1} ms", total];
+        println!["mean:   {:.3} ms", total/times.len() as f64];
+        println!["median: {:.3} ms", percentile(50)];
+        println!["p95:    {:.3} ms", percentile(95)];
+        println!["max:    {:.3} ms", times[times.len() - 1]];
+
+        let binds: usize = self.frame_binds.iter().sum();
+        println!["binds:  {:.1} per frame", binds as f64/self.frame_binds.len() as f64];
+        println!["atlas:  {} glyphs on {} pages, {} evictions", atlas.get_glyph_count(), atlas.get_page_count(), atlas.evictions];
+    }
+}
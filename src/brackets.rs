@@ -0,0 +1,256 @@
+use ::syntax;
+use ::config;
+
+use sdl2::pixels::Color;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+fn get_partner(c: char) -> Option<(char, bool)> {
+    for &(open, close) in BRACKETS.iter() {
+        if c == open {
+            return Some((close, true));
+        }
+        if c == close {
+            return Some((open, false));
+        }
+    }
+    None
+}
+
+// Byte offsets of every bracket in the line that is not part of a string or comment
+fn get_code_brackets(text: &Vec<String>, y: usize, syntax_handler: &Option<syntax::SyntaxHandler>) -> Vec<(usize, char)> {
+    let line = &text[y];
+    let literals: Vec<bool> = match syntax_handler {
+        Some(syntax_handler) => syntax_handler.get_scopes(line, y).into_iter().map(|scope| syntax_handler.is_literal(scope)).collect(),
+        None => Vec::new(),
+    };
+
+    let mut ret = Vec::new();
+    for (i, (x, c)) in line.grapheme_indices(true).enumerate() {
+        if literals.get(i) == Some(&true) {
+            continue;
+        }
+
+        let mut chars = c.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => {
+                if get_partner(c).is_some() {
+                    ret.push((x, c));
+                }
+            },
+            _ => (),
+        }
+    }
+    ret
+}
+
+fn find_partner(text: &Vec<String>, syntax_handler: &Option<syntax::SyntaxHandler>, x: usize, y: usize, c: char, lines: &Range<usize>) -> Option<(usize, usize)> {
+    let (partner, forward) = get_partner(c).unwrap();
+    let mut depth = 0;
+
+    if forward {
+        for j in y..lines.end.min(text.len()) {
+            for (i, b) in get_code_brackets(text, j, syntax_handler) {
+                if j == y && i <= x {
+                    continue;
+                }
+
+                if b == c {
+                    depth += 1;
+                }
+                else if b == partner {
+                    if depth == 0 {
+                        return Some((i, j));
+                    }
+                    depth -= 1;
+                }
+            }
+        }
+    }
+    else {
+        for j in (lines.start..=y).rev() {
+            for (i, b) in get_code_brackets(text, j, syntax_handler).into_iter().rev() {
+                if j == y && i >= x {
+                    continue;
+                }
+
+                if b == c {
+                    depth += 1;
+                }
+                else if b == partner {
+                    if depth == 0 {
+                        return Some((i, j));
+                    }
+                    depth -= 1;
+                }
+            }
+        }
+    }
+    None
+}
+
+// Finds the bracket under (or right before) the cursor and its partner, as (x, y) byte positions.
+// Only the partner's lines are searched, e.g. those on screen when drawing
+pub fn find_match(text: &Vec<String>, syntax_handler: &Option<syntax::SyntaxHandler>, x: usize, y: usize, lines: Range<usize>) -> Option<((usize, usize), (usize, usize))> {
+    if y < lines.start || y >= lines.end {
+        return None;
+    }
+
+    let brackets = get_code_brackets(text, y, syntax_handler);
+
+    let under = brackets.iter().find(|&&(i, _)| i == x);
+    let before = brackets.iter().find(|&&(i, c)| i + c.len_utf8() == x);
+
+    for bracket in under.iter().chain(before.iter()) {
+        let &&(i, c) = bracket;
+        match find_partner(text, syntax_handler, i, y, c, &lines) {
+            Some(partner) => return Some(((i, y), partner)),
+            None => (),
+        }
+    }
+    None
+}
+
+// Brackets still open at the end of every line, worked out once per edit so drawing only has to
+// look at the lines on screen
+pub struct BracketHandler {
+    // (bracket, x, y) of the open brackets after each of lines 0..stacks.len()
+    stacks: Vec<Vec<(char, usize, usize)>>,
+}
+impl BracketHandler {
+    pub fn new() -> BracketHandler {
+        BracketHandler { stacks: Vec::new() }
+    }
+
+    // Forgets line y and everything below it, since a bracket added or removed there changes
+    // the open ones on every line after
+    pub fn invalidate(&mut self, y: usize) {
+        self.stacks.truncate(y);
+    }
+
+    // Works out the lines forgotten since the last call
+    pub fn update(&mut self, text: &Vec<String>, syntax_handler: &Option<syntax::SyntaxHandler>) {
+        self.stacks.truncate(text.len());
+        let mut stack = self.stacks.last().cloned().unwrap_or(Vec::new());
+
+        for y in self.stacks.len()..text.len() {
+            for (x, c) in get_code_brackets(text, y, syntax_handler) {
+                let (partner, open) = get_partner(c).unwrap();
+                if open {
+                    stack.push((c, x, y));
+                }
+                else {
+                    match stack.last() {
+                        Some(&(top, _, _)) if top == partner => {
+                            stack.pop();
+                        },
+                        _ => (),
+                    }
+                }
+            }
+            self.stacks.push(stack.clone());
+        }
+    }
+
+    // Nesting depth of every bracket on lines y1..y2, keyed by (x, y); None marks an unmatched
+    // bracket. Only those lines are scanned, an opener left open at y2 is unmatched if it is
+    // still open at the end of the buffer
+    pub fn get_depths(&self, text: &Vec<String>, syntax_handler: &Option<syntax::SyntaxHandler>, y1: usize, y2: usize) -> HashMap<(usize, usize), Option<usize>> {
+        let mut ret = HashMap::new();
+        let y2 = y2.min(text.len()).min(self.stacks.len());
+        let mut stack = if y1 > 0 { self.stacks.get(y1 - 1).cloned().unwrap_or(Vec::new()) } else { Vec::new() };
+
+        for y in y1..y2 {
+            for (x, c) in get_code_brackets(text, y, syntax_handler) {
+                let (partner, open) = get_partner(c).unwrap();
+                if open {
+                    stack.push((c, x, y));
+                }
+                else {
+                    match stack.last().cloned() {
+                        Some((top, top_x, top_y)) if top == partner => {
+                            stack.pop();
+                            if top_y >= y1 {
+                                ret.insert((top_x, top_y), Some(stack.len()));
+                            }
+                            ret.insert((x, y), Some(stack.len()));
+                        },
+                        _ => {
+                            ret.insert((x, y), None);
+                        },
+                    }
+                }
+            }
+        }
+
+        let unclosed = self.stacks.last();
+        for (depth, bracket) in stack.iter().enumerate() {
+            let &(_, x, y) = bracket;
+            if y >= y1 {
+                let matched = !unclosed.map(|unclosed| unclosed.contains(bracket)).unwrap_or(false);
+                ret.insert((x, y), if matched { Some(depth) } else { None });
+            }
+        }
+
+        ret
+    }
+}
+
+pub fn colorize_line(line: &str, y: usize, depths: &HashMap<(usize, usize), Option<usize>>, colors: &mut Vec<Color>, config: &config::Config) {
+    if config.bracket_colors.len() == 0 {
+        return;
+    }
+
+    for (i, (x, _)) in line.grapheme_indices(true).enumerate() {
+        match depths.get(&(x, y)) {
+            Some(Some(depth)) => colors[i] = config.bracket_colors[depth % config.bracket_colors.len()],
+            Some(None) => colors[i] = config.bracket_error_color,
+            None => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depths(lines: &[&str], y1: usize, y2: usize) -> Vec<((usize, usize), Option<usize>)> {
+        let text: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        let mut handler = BracketHandler::new();
+        handler.update(&text, &None);
+
+        let mut ret: Vec<_> = handler.get_depths(&text, &None, y1, y2).into_iter().collect();
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn openers_closed_below_the_lines_are_matched() {
+        let lines = ["{", "  \"a\": [1, 2],", "  \"b\": 3", "}"];
+        assert_eq!(depths(&lines, 0, 2), vec![((0, 0), Some(0)), ((7, 1), Some(1)), ((12, 1), Some(1))]);
+
+        // The ( is still open at the end of the buffer
+        let lines = ["(", "{", "}"];
+        assert_eq!(depths(&lines, 0, 2), vec![((0, 0), None), ((0, 1), Some(1))]);
+        assert_eq!(depths(&lines, 2, 3), vec![((0, 2), Some(1))]);
+    }
+
+    #[test]
+    fn edits_forget_the_lines_below() {
+        let mut text: Vec<String> = ["(", "[", "]", ")"].iter().map(|l| l.to_string()).collect();
+        let mut handler = BracketHandler::new();
+        handler.update(&text, &None);
+        assert_eq!(handler.get_depths(&text, &None, 0, 1).get(&(0, 0)), Some(&Some(0)));
+
+        text[3] = "".to_owned();
+        handler.invalidate(3);
+        handler.update(&text, &None);
+        assert_eq!(handler.get_depths(&text, &None, 0, 1).get(&(0, 0)), Some(&None));
+        assert_eq!(handler.get_depths(&text, &None, 1, 3).get(&(0, 2)), Some(&Some(1)));
+    }
+}
@@ -0,0 +1,78 @@
+use ::utils;
+use ::detect;
+use ::indent;
+use ::pairs;
+
+use std::fs;
+use std::path::Path;
+
+// Every problem in one language directory, as "file: field: error"
+pub fn check_language(dir: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if !Path::new(&format!["{}/name", dir]).is_file() {
+        errors.push(format!["{}/name: missing", dir]);
+    }
+
+    let mut warnings = Vec::new();
+    match utils::load_syntax(dir, &mut warnings) {
+        Ok(_) => (),
+        Err(e) => errors.extend(e),
+    }
+    for warning in &warnings {
+        println!["{}", warning];
+    }
+    match utils::load_file(&format!["{}/indent.json", dir], indent::IndentHandler::parse_indent_file) {
+        Ok(_) => (),
+        Err(e) => errors.extend(e),
+    }
+    match utils::load_file(&format!["{}/pairs.json", dir], pairs::PairHandler::parse_pairs_file) {
+        Ok(_) => (),
+        Err(e) => errors.extend(e),
+    }
+
+    let path = format!["{}/detect.json", dir];
+    if Path::new(&path).is_file() {
+        for e in detect::check_detect_file(&path) {
+            errors.push(format!["{}: {}", path, e]);
+        }
+    }
+
+    errors
+}
+
+// Checks a single language directory, or every one under a langs directory. Returns
+// whether everything was valid
+pub fn check_langs(dir: &str) -> bool {
+    let dir = dir.trim_end_matches('/');
+
+    let dirs: Vec<String> = if Path::new(&format!["{}/name", dir]).is_file() {
+        vec![dir.to_owned()]
+    }
+    else {
+        let mut dirs: Vec<String> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok())
+                                  .filter(|e| e.path().is_dir())
+                                  .map(|e| e.path().display().to_string())
+                                  .collect(),
+            Err(e) => {
+                println!["{}: {}", dir, e];
+                return false;
+            }
+        };
+        dirs.sort();
+        dirs
+    };
+
+    let mut error_count = 0;
+    for dir in &dirs {
+        let errors = check_language(dir);
+        for e in &errors {
+            println!["{}", e];
+        }
+        error_count += errors.len();
+    }
+
+    println!["{} language(s) checked, {} error(s)", dirs.len(), error_count];
+    error_count == 0
+}
@@ -0,0 +1,171 @@
+use ::settings;
+
+fn get_tokens(settings: &settings::LanguageSettings) -> Option<(String, String)> {
+    match (&settings.line_comment, &settings.block_comment) {
+        (Some(line), _) => Some((line.clone(), "".to_owned())),
+        (None, Some((open, close))) => Some((open.clone(), close.clone())),
+        (None, None) => None,
+    }
+}
+
+fn is_commented(line: &str, open: &str, close: &str) -> bool {
+    let line = line.trim();
+    line.starts_with(open) && line[open.len()..].ends_with(close)
+}
+
+fn uncomment_line(line: &str, open: &str, close: &str) -> String {
+    let indent = line.len() - line.trim_start().len();
+
+    let mut body = &line[indent + open.len()..];
+    if body.starts_with(' ') {
+        body = &body[1..];
+    }
+    let mut body = body.trim_end();
+    body = &body[..body.len() - close.len()];
+    if close != "" && body.ends_with(' ') {
+        body = &body[..body.len() - 1];
+    }
+
+    format!["{}{}", &line[..indent], body]
+}
+
+// Comments lines y1 to y2 out with the language's line comment (or block comment around each
+// line), or back in when every non-blank one already is. Returns how many bytes each line grew
+// by, None if the language has no comment tokens
+pub fn toggle_comment(text: &mut Vec<String>, y1: usize, y2: usize, settings: &settings::LanguageSettings) -> Option<Vec<isize>> {
+    let (open, close) = get_tokens(settings)?;
+
+    let lines: Vec<usize> = (y1..=y2).filter(|&y| text[y].trim().len() > 0).collect();
+    let commented = lines.len() > 0 && lines.iter().all(|&y| is_commented(&text[y], &open, &close));
+
+    // New comments all start after the indentation the lines share so they line up, which is
+    // none between a tab and spaces
+    let indent = lines.iter().map(|&y| &text[y][..text[y].len() - text[y].trim_start().len()])
+                      .fold(None, |shared: Option<&str>, indent| match shared {
+                          Some(shared) => Some(&shared[..shared.bytes().zip(indent.bytes()).take_while(|(a, b)| a == b).count()]),
+                          None => Some(indent),
+                      })
+                      .map_or(0, |shared| shared.len());
+
+    let mut diffs = Vec::new();
+    for y in y1..=y2 {
+        let old_len = text[y].len() as isize;
+
+        if lines.contains(&y) {
+            text[y] = if commented {
+                uncomment_line(&text[y], &open, &close)
+            }
+            else if close == "" {
+                format!["{}{} {}", &text[y][..indent], open, &text[y][indent..]]
+            }
+            else {
+                format!["{}{} {} {}", &text[y][..indent], open, &text[y][indent..], close]
+            };
+        }
+
+        diffs.push(text[y].len() as isize - old_len);
+    }
+    Some(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line_settings() -> settings::LanguageSettings {
+        let mut settings = settings::LanguageSettings::default();
+        settings.line_comment = Some("//".to_owned());
+        settings.block_comment = Some(("/*".to_owned(), "*/".to_owned()));
+        settings
+    }
+
+    fn block_settings() -> settings::LanguageSettings {
+        let mut settings = settings::LanguageSettings::default();
+        settings.block_comment = Some(("<!--".to_owned(), "-->".to_owned()));
+        settings
+    }
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn line_comments_are_preferred() {
+        let mut text = lines(&["fn main() {", "    f();", "}"]);
+        let diffs = toggle_comment(&mut text, 0, 2, &line_settings());
+        assert_eq!(text, lines(&["// fn main() {", "//     f();", "// }"]));
+        assert_eq!(diffs, Some(vec![3, 3, 3]));
+
+        let diffs = toggle_comment(&mut text, 0, 2, &line_settings());
+        assert_eq!(text, lines(&["fn main() {", "    f();", "}"]));
+        assert_eq!(diffs, Some(vec![-3, -3, -3]));
+    }
+
+    #[test]
+    fn block_comments_wrap_each_line() {
+        let mut text = lines(&["<p>", "  text", "</p>"]);
+        let diffs = toggle_comment(&mut text, 1, 2, &block_settings());
+        assert_eq!(text, lines(&["<p>", "<!--   text -->", "<!-- </p> -->"]));
+        assert_eq!(diffs, Some(vec![9, 9]));
+
+        let diffs = toggle_comment(&mut text, 1, 2, &block_settings());
+        assert_eq!(text, lines(&["<p>", "  text", "</p>"]));
+        assert_eq!(diffs, Some(vec![-9, -9]));
+    }
+
+    #[test]
+    fn uncommenting_keeps_indentation() {
+        let mut text = lines(&["    //f();", "    // g();  ", "  /* h(); */"]);
+        toggle_comment(&mut text, 0, 1, &line_settings());
+        assert_eq!(text[..2].to_vec(), lines(&["    f();", "    g();"]));
+
+        let mut settings = line_settings();
+        settings.line_comment = None;
+        toggle_comment(&mut text, 2, 2, &settings);
+        assert_eq!(text[2], "  h();");
+    }
+
+    #[test]
+    fn partly_commented_lines_are_all_commented() {
+        let mut text = lines(&["// a", "b"]);
+        toggle_comment(&mut text, 0, 1, &line_settings());
+        assert_eq!(text, lines(&["// // a", "// b"]));
+    }
+
+    #[test]
+    fn comments_line_up_at_the_shared_indentation() {
+        let mut text = lines(&["    if x {", "        f();", "    }"]);
+        toggle_comment(&mut text, 0, 2, &line_settings());
+        assert_eq!(text, lines(&["    // if x {", "    //     f();", "    // }"]));
+
+        let mut text = lines(&["\tf();", "    g();"]);
+        toggle_comment(&mut text, 0, 1, &line_settings());
+        assert_eq!(text, lines(&["// \tf();", "//     g();"]));
+        toggle_comment(&mut text, 0, 1, &line_settings());
+        assert_eq!(text, lines(&["\tf();", "    g();"]));
+    }
+
+    #[test]
+    fn blank_lines_are_left_alone() {
+        let mut text = lines(&["    f();", "", "  ", "    g();"]);
+        let diffs = toggle_comment(&mut text, 0, 3, &line_settings());
+        assert_eq!(text, lines(&["    // f();", "", "  ", "    // g();"]));
+        assert_eq!(diffs, Some(vec![3, 0, 0, 3]));
+
+        // Blank lines don't count as uncommented
+        toggle_comment(&mut text, 0, 3, &line_settings());
+        assert_eq!(text, lines(&["    f();", "", "  ", "    g();"]));
+
+        // Nothing but blank lines gets nothing
+        let mut text = lines(&["", " "]);
+        assert_eq!(toggle_comment(&mut text, 0, 1, &line_settings()), Some(vec![0, 0]));
+        assert_eq!(text, lines(&["", " "]));
+    }
+
+    #[test]
+    fn no_tokens() {
+        let mut text = lines(&["f();"]);
+        assert_eq!(toggle_comment(&mut text, 0, 0, &settings::LanguageSettings::default()), None);
+        assert_eq!(text, lines(&["f();"]));
+    }
+}
@@ -2,38 +2,64 @@ extern crate serde;
 extern crate serde_json;
 
 use ::utils;
-use ::syntax;
+use ::theme;
+use ::paths;
+use ::settings;
 
 use sdl2::pixels::Color;
+use sdl2::ttf::FontStyle;
 
+use std::collections::HashMap;
+
+// Every key is optional: whatever a config file leaves out keeps its default
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 struct ConfigJSON {
-    syntax_constant_color: [u8; 3],
-    syntax_keyword_color: [u8; 3],
-    syntax_secondary_word_color: [u8; 3],
-    syntax_preproc_color: [u8; 3],
-    syntax_data_type_color: [u8; 3],
-    syntax_comment_color: [u8; 3],
-    syntax_other_color: [u8; 3],
-
-    bg_color: [u8; 3],
-    line_number_color: [u8; 3],
-
-    bar_color: [u8; 3],
-    bar_text_color: [u8; 3],
-
-    select_color: [u8; 3],
-    search_color: [u8; 3],
+    theme: String,
 
     cursor_width: u32,
+    tab_width: u32,
+    minimap: bool,
 
     font_path: String,
     font_size: u16,
+    fallback_fonts: Vec<String>,
+
+    languages: HashMap<String, settings::LanguageSettings>,
+
+    #[serde(flatten)]
+    colors: theme::ThemeOverridesJSON,
 }
+impl Default for ConfigJSON {
+    fn default() -> ConfigJSON {
+        let config: Config = Default::default();
+
+        ConfigJSON {
+            theme: config.theme,
+
+            cursor_width: config.cursor_width,
+            tab_width: config.tab_width,
+            minimap: config.minimap,
 
-#[derive(Debug)]
+            font_path: config.font_path,
+            font_size: config.font_size,
+            fallback_fonts: config.fallback_fonts,
+
+            languages: config.languages,
+
+            colors: config.theme_overrides,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Config {
-    pub syntax_color: syntax::SyntaxColor,
+    pub theme: String,
+    // Colors the config files set over the theme's
+    pub theme_overrides: theme::ThemeOverridesJSON,
+
+    pub syntax_colors: HashMap<String, Color>,
+    pub syntax_styles: HashMap<String, FontStyle>,
 
     pub bg_color: Color,
     pub line_number_color: Color,
@@ -43,65 +69,287 @@ pub struct Config {
 
     pub select_color: Color,
     pub search_color: Color,
+    pub match_color: Color,
+
+    pub bracket_colors: Vec<Color>,
+    pub bracket_error_color: Color,
 
     pub cursor_width: u32,
+    pub tab_width: u32,
+    // Shows the whole buffer in miniature next to the scrollbar
+    pub minimap: bool,
 
     pub font_path: String,
     pub font_size: u16,
+    // Tried in order for glyphs the main font doesn't have
+    pub fallback_fonts: Vec<String>,
+
+    pub languages: HashMap<String, settings::LanguageSettings>,
+
+    // Problems found while loading that didn't stop the config from being used
+    pub warnings: Vec<String>,
 }
-impl Config {
-    pub fn load_config(path: &str) -> Config {
-        let file = utils::read_file(path);
-
-        let decoded: Result<ConfigJSON, serde_json::Error> = serde_json::from_str(&file);
-
-        match decoded {
-            Ok(decoded) => {
-                return Config {
-                    syntax_color: syntax::SyntaxColor {
-                        constant: color![decoded.syntax_constant_color],
-                        keyword: color![decoded.syntax_keyword_color],
-                        secondary_word: color![decoded.syntax_secondary_word_color],
-                        preproc: color![decoded.syntax_preproc_color],
-                        data_type: color![decoded.syntax_data_type_color],
-                        comment: color![decoded.syntax_comment_color],
-                        other: color![decoded.syntax_other_color],
-                    },
 
-                    bg_color: color![decoded.bg_color],
-                    line_number_color: color![decoded.line_number_color],
+// Objects are merged key by key, anything else in `layer` replaces what `base` had
+fn merge_json(base: &mut serde_json::Value, layer: serde_json::Value) {
+    match (base, layer) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => {
+                        merge_json(existing, value);
+                        continue;
+                    },
+                    None => (),
+                }
+                base.insert(key, value);
+            }
+        },
+        (base, layer) => *base = layer,
+    }
+}
 
-                    bar_color: color![decoded.bar_color],
-                    bar_text_color: color![decoded.bar_text_color],
+// Drops the keys of a layer that are unknown or hold a value of the wrong type, so a typo
+// only costs that one setting
+fn check_layer(path: &str, layer: serde_json::Value, warnings: &mut Vec<String>) -> Result<serde_json::Value, String> {
+    let known = match serde_json::to_value(ConfigJSON::default()) {
+        Ok(serde_json::Value::Object(known)) => known,
+        _ => serde_json::Map::new(),
+    };
 
-                    select_color: color_a![decoded.select_color, 100],
-                    search_color: color_a![decoded.search_color, 100],
+    let layer = match layer {
+        serde_json::Value::Object(layer) => layer,
+        _ => return Err(format!["{}: expected an object", path]),
+    };
 
-                    cursor_width: decoded.cursor_width as u32,
+    let mut checked = serde_json::Map::new();
+    for (key, value) in layer {
+        if !known.contains_key(&key) {
+            warnings.push(format!["{}: unknown key \"{}\"", path, key]);
+            continue;
+        }
 
-                    font_path: decoded.font_path,
-                    font_size: decoded.font_size as u16,
-                };
+        // A mistake in one language's settings shouldn't drop every other language's
+        let value = match value {
+            serde_json::Value::Object(languages) if key == "languages" => {
+                let mut checked_languages = serde_json::Map::new();
+                for (id, settings) in languages {
+                    match serde_json::from_value::<settings::LanguageSettings>(settings.clone()) {
+                        Ok(_) => { checked_languages.insert(id, settings); },
+                        Err(e) => warnings.push(format!["{}: languages.{}: {}", path, id, e]),
+                    }
+                }
+                serde_json::Value::Object(checked_languages)
             },
+            value => value,
+        };
+
+        let mut single = serde_json::Map::new();
+        single.insert(key.clone(), value.clone());
+        match serde_json::from_value::<ConfigJSON>(serde_json::Value::Object(single)) {
+            Ok(_) => { checked.insert(key, value); },
+            Err(e) => warnings.push(format!["{}: {}: {}", path, key, e]),
+        }
+    }
+    Ok(serde_json::Value::Object(checked))
+}
+
+// Reads every layer in order on top of the defaults, later ones overriding the keys they set.
+// Fails if a file isn't valid JSON at all; bad keys only give warnings
+pub fn merge_layers(layers: &Vec<String>) -> Result<(serde_json::Value, Vec<String>), String> {
+    let mut warnings = Vec::new();
+
+    let mut merged = serde_json::to_value(ConfigJSON::default()).map_err(|e| e.to_string())?;
+    for path in layers {
+        let layer: serde_json::Value = serde_json::from_str(&utils::read_file(path)).map_err(|e| format!["{}: {}", path, e])?;
+        merge_json(&mut merged, check_layer(path, layer, &mut warnings)?);
+    }
+    Ok((merged, warnings))
+}
+
+impl Config {
+    // Never fails: layers that can't be read are skipped and a theme that can't be loaded is
+    // replaced by the default one, with what went wrong kept in warnings
+    pub fn load_config(layers: &Vec<String>) -> Config {
+        let mut errors = Vec::new();
+        let mut valid = Vec::new();
+        for path in layers {
+            match serde_json::from_str::<serde_json::Value>(&utils::read_file(path)) {
+                Ok(serde_json::Value::Object(_)) => valid.push(path.clone()),
+                Ok(_) => errors.push(format!["{}: expected an object", path]),
+                Err(e) => errors.push(format!["{}: {}", path, e]),
+            }
+        }
+
+        let mut config = match Self::decode_config(&valid) {
+            Ok(config) => config,
             Err(e) => {
-                println!["{}", e];
-                return Default::default();
+                errors.push(e);
+
+                let mut config: Config = Default::default();
+                config.font_path = paths::resolve_font_path(&config.font_path);
+                config
+            }
+        };
+
+        let default_theme = Config::default().theme;
+        let theme = match theme::load_theme(&config.theme) {
+            Err(ref e) if config.theme != default_theme => {
+                errors.push(format!["{}, using the {} theme", e, default_theme]);
+                theme::load_theme(&default_theme)
+            },
+            theme => theme,
+        };
+        match theme {
+            Ok(theme) => config.apply_theme(&theme),
+            Err(e) => errors.push(e),
+        }
+
+        errors.extend(config.warnings.drain(..));
+        config.warnings = errors;
+        config
+    }
+
+    // Like load_config, but fails on a file that isn't valid JSON or a missing theme so a
+    // half-written file can't replace a working config at runtime
+    pub fn parse_config(layers: &Vec<String>) -> Result<Config, String> {
+        let mut config = Self::decode_config(layers)?;
+
+        let theme = theme::load_theme(&config.theme)?;
+        config.apply_theme(&theme);
+
+        Ok(config)
+    }
+
+    // Merges and decodes the layers, leaving the default colors
+    fn decode_config(layers: &Vec<String>) -> Result<Config, String> {
+        let (merged, warnings) = merge_layers(layers)?;
+
+        let decoded: ConfigJSON = serde_json::from_value(merged).map_err(|e| format!["{}: {}", layers.join(", "), e])?;
+
+        let mut config: Config = Default::default();
+        config.warnings = warnings;
+        config.theme = decoded.theme;
+
+        config.cursor_width = decoded.cursor_width as u32;
+        config.tab_width = decoded.tab_width;
+        config.minimap = decoded.minimap;
+
+        config.font_path = paths::resolve_font_path(&decoded.font_path);
+        config.font_size = decoded.font_size as u16;
+        config.fallback_fonts = decoded.fallback_fonts.iter().map(|path| paths::resolve_font_path(path)).collect();
+
+        config.languages = decoded.languages;
+
+        config.theme_overrides = decoded.colors;
+
+        Ok(config)
+    }
+
+    pub fn apply_theme(&mut self, theme: &theme::ThemeJSON) {
+        let mut theme = theme.clone();
+        self.theme_overrides.apply(&mut theme);
+
+        let mut syntax_colors = HashMap::new();
+        let mut syntax_styles = HashMap::new();
+        for (scope, entry) in &theme.syntax_colors {
+            match entry {
+                theme::ScopeStyleJSON::Color(c) => {
+                    syntax_colors.insert(scope.clone(), color![c]);
+                },
+                theme::ScopeStyleJSON::Styled { color: c, bold, italic, underline, strikethrough } => {
+                    let mut style = FontStyle::NORMAL;
+                    if *bold { style.insert(FontStyle::BOLD); }
+                    if *italic { style.insert(FontStyle::ITALIC); }
+                    if *underline { style.insert(FontStyle::UNDERLINE); }
+                    if *strikethrough { style.insert(FontStyle::STRIKETHROUGH); }
+
+                    syntax_colors.insert(scope.clone(), color![c]);
+                    syntax_styles.insert(scope.clone(), style);
+                },
+            }
+        }
+
+        self.syntax_colors = syntax_colors;
+        self.syntax_styles = syntax_styles;
+
+        self.bg_color = color![theme.bg_color];
+        self.line_number_color = color![theme.line_number_color];
+
+        self.bar_color = color![theme.bar_color];
+        self.bar_text_color = color![theme.bar_text_color];
+
+        self.select_color = color_a![theme.select_color, 100];
+        self.search_color = color_a![theme.search_color, 100];
+        self.match_color = color_a![theme.match_color, 100];
+
+        self.bracket_colors = theme.bracket_colors.iter().map(|c| color![c]).collect();
+        self.bracket_error_color = color![theme.bracket_error_color];
+    }
+
+    // Languages without a block of their own, and plain text, get the defaults
+    pub fn get_language_settings(&self, language: &Option<String>) -> settings::LanguageSettings {
+        match language.as_ref().and_then(|language| self.languages.get(language)) {
+            Some(settings) => settings.clone(),
+            None => Default::default(),
+        }
+    }
+
+    // Falls back from "function.call" to "function", then to the plain text color
+    pub fn get_scope_color(&self, scope: &str) -> Color {
+        let mut scope = scope;
+        loop {
+            match self.syntax_colors.get(scope) {
+                Some(color) => return *color,
+                None => (),
+            }
+
+            match scope.rfind('.') {
+                Some(i) => scope = &scope[..i],
+                None => break,
+            }
+        }
+
+        match self.syntax_colors.get("other") {
+            Some(color) => *color,
+            None => Color::RGB(255, 255, 255),
+        }
+    }
+
+    // Only scopes given as an object set a style, so "keyword.modifier": [r, g, b] stays as bold
+    // as "keyword"
+    pub fn get_scope_style(&self, scope: &str) -> FontStyle {
+        let mut scope = scope;
+        loop {
+            match self.syntax_styles.get(scope) {
+                Some(style) => return *style,
+                None => (),
+            }
+
+            match scope.rfind('.') {
+                Some(i) => scope = &scope[..i],
+                None => return FontStyle::NORMAL,
             }
         }
     }
 }
 impl Default for Config {
     fn default() -> Config {
+        let mut syntax_colors = HashMap::new();
+        syntax_colors.insert("constant".to_owned(), color![[0,100,255]]);
+        syntax_colors.insert("keyword".to_owned(), color![[255,0,0]]);
+        syntax_colors.insert("secondary_word".to_owned(), color![[255,100,0]]);
+        syntax_colors.insert("preproc".to_owned(), color![[255,255,0]]);
+        syntax_colors.insert("data_type".to_owned(), color![[255,0,255]]);
+        syntax_colors.insert("comment".to_owned(), color![[150,150,150]]);
+        syntax_colors.insert("other".to_owned(), color![[255,255,255]]);
+
         Config {
-            syntax_color: syntax::SyntaxColor {
-                constant: color![[0,100,255]],
-                keyword: color![[255,0,0]],
-                secondary_word: color![[255,100,0]],
-                preproc: color![[255,255,0]],
-                data_type: color![[255,0,255]],
-                comment: color![[150,150,150]],
-                other: color![[255,255,255]],
-            },
+            theme: "default".to_owned(),
+            theme_overrides: Default::default(),
+
+            syntax_colors: syntax_colors,
+            syntax_styles: HashMap::new(),
 
             bg_color: color![[25u8, 25, 25]],
             line_number_color: color![[255,255,255]],
@@ -111,11 +359,125 @@ impl Default for Config {
 
             select_color: color![[255u8, 255, 255]],
             search_color: color![[255u8, 255, 30]],
+            match_color: color![[100u8, 200, 255]],
+
+            bracket_colors: vec![color![[255u8, 215, 0]], color![[218u8, 112, 214]], color![[23u8, 159, 255]]],
+            bracket_error_color: color![[255u8, 0, 0]],
 
             cursor_width: 8,
+            tab_width: 4,
+            minimap: false,
 
             font_path: "roboto.ttf".to_owned(),
             font_size: 18,
+            fallback_fonts: Vec::new(),
+
+            languages: HashMap::new(),
+
+            warnings: Vec::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    // Writes each layer to a fresh file under the temp dir, returning their paths in order
+    fn make_layers(name: &str, layers: &[&str]) -> Vec<String> {
+        let root = env::temp_dir().join(format!["aurum-config-{}-{}", name, std::process::id()]);
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        layers.iter().enumerate().map(|(i, contents)| {
+            let path = root.join(format!["{}.json", i]);
+            fs::write(&path, contents).unwrap();
+            path.display().to_string()
+        }).collect()
+    }
+
+    fn check(layer: &str) -> (serde_json::Value, Vec<String>) {
+        let mut warnings = Vec::new();
+        let checked = check_layer("c.json", serde_json::from_str(layer).unwrap(), &mut warnings).unwrap();
+        (checked, warnings)
+    }
+
+    #[test]
+    fn later_layers_override_field_by_field() {
+        let layers = make_layers("override", &[
+            r#"{"tab_width": 8, "font_size": 12, "languages": {"rs": {"indent_width": 2, "use_tabs": true}}}"#,
+            r#"{"font_size": 20, "languages": {"rs": {"indent_width": 3}, "py": {"rulers": [79]}}}"#,
+        ]);
+        let (merged, warnings) = merge_layers(&layers).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+
+        assert_eq!(merged["tab_width"], 8);
+        assert_eq!(merged["font_size"], 20);
+        // Keys no layer sets keep their defaults
+        assert_eq!(merged["cursor_width"], 8);
+        assert_eq!(merged["theme"], "default");
+        // Language settings merge key by key too
+        assert_eq!(merged["languages"]["rs"]["indent_width"], 3);
+        assert_eq!(merged["languages"]["rs"]["use_tabs"], true);
+        assert_eq!(merged["languages"]["py"]["rulers"], serde_json::json!([79]));
+
+        let config: ConfigJSON = serde_json::from_value(merged).unwrap();
+        assert_eq!(config.languages["rs"].indent_width, 3);
+        assert!(config.languages["rs"].use_tabs);
+    }
+
+    #[test]
+    fn arrays_are_replaced_whole() {
+        let layers = make_layers("arrays", &[
+            r#"{"fallback_fonts": ["a.ttf", "b.ttf"]}"#,
+            r#"{"fallback_fonts": ["c.ttf"]}"#,
+        ]);
+        let (merged, _) = merge_layers(&layers).unwrap();
+        assert_eq!(merged["fallback_fonts"], serde_json::json!(["c.ttf"]));
+    }
+
+    #[test]
+    fn invalid_json_fails() {
+        let layers = make_layers("invalid", &[r#"{"tab_width": 8}"#, r#"{"tab_width": "#]);
+        let e = merge_layers(&layers).unwrap_err();
+        assert!(e.starts_with(&layers[1]), "{}", e);
+
+        let layers = make_layers("not-object", &[r#"[1, 2]"#]);
+        assert!(merge_layers(&layers).unwrap_err().contains("expected an object"));
+    }
+
+    #[test]
+    fn unknown_keys_are_dropped_with_a_warning() {
+        let (checked, warnings) = check(r#"{"tab_widht": 8, "font_size": 12}"#);
+        assert_eq!(checked, serde_json::json!({"font_size": 12}));
+        assert_eq!(warnings, vec!["c.json: unknown key \"tab_widht\"".to_owned()]);
+    }
+
+    #[test]
+    fn wrong_types_are_dropped_with_a_warning() {
+        let (checked, warnings) = check(r#"{"tab_width": "four", "minimap": true, "bg_color": [1, 2]}"#);
+        assert_eq!(checked, serde_json::json!({"minimap": true}));
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings.iter().any(|w| w.starts_with("c.json: tab_width: ")), "{:?}", warnings);
+        assert!(warnings.iter().any(|w| w.starts_with("c.json: bg_color: ")), "{:?}", warnings);
+    }
+
+    #[test]
+    fn a_bad_language_only_drops_that_language() {
+        let (checked, warnings) = check(r#"{"languages": {"rs": {"indent_width": 2}, "py": {"indent_widht": 2}, "c": {"use_tabs": 1}}}"#);
+        assert_eq!(checked, serde_json::json!({"languages": {"rs": {"indent_width": 2}}}));
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings.iter().any(|w| w.starts_with("c.json: languages.py: ")), "{:?}", warnings);
+        assert!(warnings.iter().any(|w| w.starts_with("c.json: languages.c: ")), "{:?}", warnings);
+    }
+
+    #[test]
+    fn warnings_name_the_layer() {
+        let layers = make_layers("warnings", &[r#"{"colour": 1}"#, r#"{"tab_width": 2, "font": "x"}"#]);
+        let (merged, warnings) = merge_layers(&layers).unwrap();
+        assert_eq!(merged["tab_width"], 2);
+        assert_eq!(warnings, vec![format!["{}: unknown key \"colour\"", layers[0]], format!["{}: unknown key \"font\"", layers[1]]]);
+    }
+}
@@ -1,20 +1,29 @@
 extern crate sdl2;
-extern crate unicode_segmentation;
 
-use sdl2::render::{TextureCreator, Canvas};
-use sdl2::video::{Window, WindowContext};
-
-use unicode_segmentation::UnicodeSegmentation;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
 
 use ::text;
 use ::config;
+use ::layout;
+use ::scrollbar;
+
+// Space kept between the cursor and the left or right edge, in multiples of the font size
+const FOLLOW_MARGIN: u32 = 2;
+// Horizontal wheel scrolling per notch, in multiples of the font size
+const SCROLL_STEP: i32 = 3;
 
 pub struct Cursor<'r> {
     pub x: u32,
     pub y: u32,
 
     pub wanted_x: u32,
+    // Offset from the row's start that wrapped Up and Down keep to, with the line and byte they
+    // left the cursor at; moving it any other way makes it stale
+    wanted_px: Option<(u32, usize, u32)>,
     pub number_w: u32,
+    // Width of the scrollbar and minimap right of the text
+    pub side_w: u32,
 
     pub screen_x: u32,
     pub screen_y: u32,
@@ -22,11 +31,19 @@ pub struct Cursor<'r> {
     pub surface: sdl2::surface::Surface<'r>,
 }
 impl<'r> Cursor<'r> {
-    pub fn new(x: u32, y: u32, config: &config::Config) -> Cursor<'r> {
+    fn create_surface(config: &config::Config) -> sdl2::surface::Surface<'r> {
         let mut cursor_surface = sdl2::surface::Surface::new((6*config.font_size/10) as u32, config.font_size as u32, sdl2::pixels::PixelFormatEnum::RGBA8888).unwrap();
         cursor_surface.fill_rect(rect![0, 0, config.cursor_width, config.font_size], sdl2::pixels::Color::RGBA(255, 255, 255, 128)).unwrap();
+        cursor_surface
+    }
+
+    pub fn new(x: u32, y: u32, config: &config::Config) -> Cursor<'r> {
+        Cursor{ x: x, y: y, wanted_x: x, wanted_px: None, number_w: 0, side_w: scrollbar::SCROLLBAR_WIDTH, screen_x: 0, screen_y: 0, surface: Self::create_surface(config) }
+    }
 
-        Cursor{ x: x, y: y, wanted_x: x, number_w: 0, screen_x: 0, screen_y: 0, surface: cursor_surface }
+    // Rebuilds the cursor after the font size or cursor width changed
+    pub fn resize(&mut self, config: &config::Config) {
+        self.surface = Self::create_surface(config);
     }
 
     pub fn up(&mut self, text: &Vec<String>, canvas: &Canvas<Window>, config: &config::Config) {
@@ -68,6 +85,7 @@ impl<'r> Cursor<'r> {
                 self.x -= 1;
             }
             self.wanted_x = self.x;
+            self.wanted_px = None;
         }
     }
 
@@ -78,6 +96,25 @@ impl<'r> Cursor<'r> {
                 self.x += 1;
             }
             self.wanted_x = self.x;
+            self.wanted_px = None;
+        }
+    }
+
+    pub fn go_to(&mut self, x: u32, y: usize, text: &Vec<String>, canvas: &Canvas<Window>, config: &config::Config) {
+        while self.get_absolute_y() > y {
+            self.up(text, canvas, config);
+        }
+
+        while self.get_absolute_y() < y {
+            self.down(text, canvas, config);
+        }
+
+        while self.x > x {
+            self.left(text);
+        }
+
+        while self.x < x {
+            self.right(text);
         }
     }
 
@@ -107,34 +144,139 @@ impl<'r> Cursor<'r> {
         }
     }
 
-    pub fn move_to(&mut self, x: i32, y: i32, texture_creator: &'r TextureCreator<WindowContext>, text: &mut text::Text<'_, 'r>, config: &config::Config) {
-        if (y/config.font_size as i32) as u32 + self.screen_y < text.raw.len() as u32 {
-            self.y = (y/config.font_size as i32) as u32;
+    // Makes `screen_y` the first line shown, the cursor moving along only where it would leave
+    // the screen
+    pub fn scroll_to(&mut self, canvas: &Canvas<Window>, text: &Vec<String>, screen_y: usize, config: &config::Config) {
+        let screen_y = screen_y.min(text.len() - 1) as u32;
+        let last_row = (canvas.window().size().1/config.font_size as u32).saturating_sub(2);
+        let old_y = self.get_absolute_y();
+        let y = (old_y as u32).max(screen_y).min(screen_y + last_row).min(text.len() as u32 - 1);
+
+        self.screen_y = screen_y;
+        self.y = y - screen_y;
+        if y as usize != old_y {
+            self.x = self.wanted_x.min(text[y as usize].len() as u32);
+        }
+    }
+
+    // Width of the text area between the line numbers and the scrollbar
+    fn get_view_width(&self, canvas: &Canvas<Window>) -> u32 {
+        canvas.window().size().0.saturating_sub(self.number_w + self.side_w)
+    }
+
+    // Scrolls horizontally just enough to keep the cursor in view
+    pub fn follow_x(&mut self, text: &text::Text, canvas: &Canvas<Window>, config: &config::Config) {
+        let x = text.get_text_width(&text.raw[self.get_absolute_y()][..self.x as usize]);
+        let view_w = self.get_view_width(canvas);
+        let margin = (FOLLOW_MARGIN*config.font_size as u32).min(view_w/4);
+
+        if x < self.screen_x + margin {
+            self.screen_x = x.saturating_sub(margin);
+        }
+        else if x + config.cursor_width + margin > self.screen_x + view_w {
+            self.screen_x = x + config.cursor_width + margin - view_w;
+        }
+    }
+
+    // Scrolls sideways, right for a positive `dir`, up to where the widest line on screen ends
+    pub fn scroll_horizontal(&mut self, text: &text::Text, canvas: &Canvas<Window>, dir: i32, config: &config::Config) {
+        let rows = (canvas.window().size().1/config.font_size as u32) as usize;
+        let start = self.screen_y as usize;
+        let end = (start + rows).min(text.raw.len());
+        let widest = text.raw[start..end].iter().map(|line| text.get_text_width(line)).max().unwrap_or(0);
+        let max_x = (widest + config.cursor_width).saturating_sub(self.get_view_width(canvas));
+
+        let step = SCROLL_STEP*config.font_size as i32*dir;
+        if step < 0 {
+            self.screen_x = (self.screen_x as i32 + step).max(0) as u32;
         }
-        else {
-            self.y = text.raw.len() as u32 - self.screen_y - 1;
+        else if step > 0 && self.screen_x < max_x {
+            self.screen_x = (self.screen_x + step as u32).min(max_x);
         }
+    }
 
-        let mut width = 0;
-        let mut len = 0;
-        let line = text.raw[self.get_absolute_y()].clone();
-        let mut c_iter = line.graphemes(true);
-        let mut c = c_iter.next();
-        while c != None {
-            if ((x-self.number_w as i32)-width as i32) < config.font_size as i32/2 {
-                break;
-            }
+    // Offset from the row's start to keep to, the one the last Up or Down started from unless
+    // the cursor moved since
+    fn get_wanted_px(&self, text: &text::Text, start: usize) -> u32 {
+        match self.wanted_px {
+            Some((px, y, x)) if y == self.get_absolute_y() && x == self.x => px,
+            _ => text.get_text_width(&text.raw[self.get_absolute_y()][start..self.x as usize]),
+        }
+    }
+
+    // Up and Down with wrapped lines, moving to the row above or below at the same offset
+    // from the row's start
+    pub fn up_row(&mut self, text: &text::Text, canvas: &Canvas<Window>, config: &config::Config, wrap_width: Option<u32>) {
+        let starts = layout::get_row_starts(text, &text.raw[self.get_absolute_y()], wrap_width);
+        let row = starts.iter().rposition(|&start| start <= self.x as usize).unwrap_or(0);
+        let offset = self.get_wanted_px(text, starts[row]);
+
+        if row > 0 {
+            self.x = layout::get_x_at(text, &text.raw[self.get_absolute_y()], starts[row - 1], starts[row], offset as i32) as u32;
+        }
+        else if self.get_absolute_y() > 0 {
+            self.up(&text.raw, canvas, config);
+
+            let line = &text.raw[self.get_absolute_y()];
+            let starts = layout::get_row_starts(text, line, wrap_width);
+            self.x = layout::get_x_at(text, line, *starts.last().unwrap(), line.len(), offset as i32) as u32;
+        }
+        self.wanted_px = Some((offset, self.get_absolute_y(), self.x));
+    }
 
-            let cur = c.unwrap();
-            let texture = text.get_normal_char(&cur, &texture_creator, &::WHITE);
-            let texture_info = texture.query();
+    pub fn down_row(&mut self, text: &text::Text, canvas: &Canvas<Window>, config: &config::Config, wrap_width: Option<u32>) {
+        let line = &text.raw[self.get_absolute_y()];
+        let starts = layout::get_row_starts(text, line, wrap_width);
+        let row = starts.iter().rposition(|&start| start <= self.x as usize).unwrap_or(0);
+        let offset = self.get_wanted_px(text, starts[row]);
+
+        if row + 1 < starts.len() {
+            let end = starts.get(row + 2).cloned().unwrap_or(line.len());
+            self.x = layout::get_x_at(text, line, starts[row + 1], end, offset as i32) as u32;
+        }
+        else if self.get_absolute_y() < text.raw.len() - 1 {
+            self.down(&text.raw, canvas, config);
 
-            width += texture_info.width;
-            len += cur.len() as u32;
-            c = c_iter.next();
+            let line = &text.raw[self.get_absolute_y()];
+            let starts = layout::get_row_starts(text, line, wrap_width);
+            let end = starts.get(1).cloned().unwrap_or(line.len());
+            self.x = layout::get_x_at(text, line, 0, end, offset as i32) as u32;
         }
-        self.x = len;
-        self.wanted_x = len;
+        self.wanted_px = Some((offset, self.get_absolute_y(), self.x));
+    }
+
+    // Scrolls down by whole lines until the cursor's row is above the status bar, which wrapped
+    // lines taking several rows can push it below
+    pub fn fit_rows(&mut self, text: &text::Text, canvas: &Canvas<Window>, config: &config::Config, wrap_width: Option<u32>) {
+        let rows = (canvas.window().size().1/config.font_size as u32) as usize;
+        while self.y > 0 {
+            let layout = layout::Layout::new(text, self.screen_y as usize, rows.saturating_sub(1), wrap_width);
+            match layout.get_position(text, self.get_absolute_y(), self.x as usize) {
+                Some(_) => break,
+                None => {
+                    self.screen_y += 1;
+                    self.y -= 1;
+                },
+            }
+        }
+    }
+
+    // Line and byte offset under a point in the window, points below the text hitting the last line
+    pub fn get_hit(&self, x: i32, y: i32, text: &text::Text, config: &config::Config, wrap_width: Option<u32>) -> (usize, usize) {
+        let row = (y.max(0)/config.font_size as i32) as usize;
+        let layout = layout::Layout::new(text, self.screen_y as usize, row + 1, wrap_width);
+        let row = &layout.rows[row.min(layout.rows.len() - 1)];
+
+        let x = x - self.number_w as i32 + self.screen_x as i32;
+        (row.line, layout::get_x_at(text, &text.raw[row.line], row.start, row.end, x))
+    }
+
+    pub fn move_to(&mut self, x: i32, y: i32, text: &mut text::Text, config: &config::Config, wrap_width: Option<u32>) {
+        let (line, len) = self.get_hit(x, y, text, config, wrap_width);
+        self.y = (line - self.screen_y as usize) as u32;
+        self.x = len as u32;
+        self.wanted_x = len as u32;
+        self.wanted_px = None;
 
         text.needs_update = true;
     }
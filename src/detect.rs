@@ -0,0 +1,393 @@
+extern crate serde;
+extern crate serde_json;
+extern crate regex;
+
+use ::utils;
+
+use std::path::Path;
+
+// How many lines at each end of the buffer are searched for a modeline
+const MODELINE_LINES: usize = 5;
+// How many lines are searched when several languages claim the same file
+const CONTENT_LINES: usize = 100;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DetectJSON {
+    #[serde(default)]
+    filenames: Vec<String>,
+    #[serde(default)]
+    extensions: Vec<String>,
+    #[serde(default)]
+    interpreters: Vec<String>,
+    #[serde(default)]
+    first_line: Vec<String>,
+    #[serde(default)]
+    content: Vec<String>,
+}
+
+struct Language {
+    id: String,
+    name: String,
+    detect: DetectJSON,
+}
+
+fn parse_detect_file(path: &str) -> Result<DetectJSON, Vec<String>> {
+    let decoded: Result<DetectJSON, serde_json::Error> = serde_json::from_str(&utils::read_file(path));
+
+    match decoded {
+        Ok(decoded) => {
+            let mut errors = Vec::new();
+            for (field, patterns) in vec![("first_line", &decoded.first_line), ("content", &decoded.content)] {
+                for (i, pattern) in patterns.iter().enumerate() {
+                    match regex::Regex::new(pattern) {
+                        Ok(_) => (),
+                        Err(e) => errors.push(format!["{}[{}]: {}", field, i, e]),
+                    }
+                }
+            }
+
+            if errors.len() > 0 {
+                return Err(errors);
+            }
+            Ok(decoded)
+        },
+        Err(e) => Err(vec![e.to_string()]),
+    }
+}
+
+pub fn check_detect_file(path: &str) -> Vec<String> {
+    match parse_detect_file(path) {
+        Ok(_) => Vec::new(),
+        Err(errors) => errors,
+    }
+}
+
+// A detect.json that can't be loaded detects nothing, with its errors added to `errors`
+fn load_languages(errors: &mut Vec<String>) -> Vec<Language> {
+    let mut ret = Vec::new();
+    for (id, dir) in utils::get_language_dirs() {
+        let path = format!["{}/detect.json", dir];
+
+        let detect = if Path::new(&path).is_file() {
+            match parse_detect_file(&path) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    errors.extend(e.iter().map(|e| format!["{}: {}", path, e]));
+                    DetectJSON::default()
+                }
+            }
+        }
+        else {
+            DetectJSON::default()
+        };
+
+        ret.push(Language { name: utils::read_file(&format!["{}/name", dir]).trim().to_owned(), id: id, detect: detect });
+    }
+    ret
+}
+
+fn matches_any(patterns: &Vec<String>, line: &str) -> bool {
+    for pattern in patterns {
+        match regex::Regex::new(pattern) {
+            Ok(regex) => {
+                if regex.is_match(line) {
+                    return true;
+                }
+            },
+            Err(_) => (),
+        }
+    }
+    false
+}
+
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        Some('*') => (0..=name.len()).any(|i| glob_match(&pattern[1..], &name[i..])),
+        Some('?') => name.len() > 0 && glob_match(&pattern[1..], &name[1..]),
+        Some(&c) => name.first() == Some(&c) && glob_match(&pattern[1..], &name[1..]),
+        None => name.len() == 0,
+    }
+}
+
+// Interpreter named by a shebang, without its directory: "#!/usr/bin/env -S python3 -u" gives "python3"
+fn get_interpreter(first_line: &str) -> Option<String> {
+    if !first_line.starts_with("#!") {
+        return None;
+    }
+
+    let mut words = first_line[2..].split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|w| !w.starts_with("-") && !w.contains("="))?;
+    }
+    Some(program.to_owned())
+}
+
+// "python" also claims "python3" and "python3.8"
+fn interpreter_match(interpreter: &str, program: &str) -> bool {
+    program.starts_with(interpreter) && program[interpreter.len()..].chars().all(|c| c.is_digit(10) || c == '.')
+}
+
+// Language named by a vim ("vim: ft=rust") or emacs ("-*- mode: rust -*-") modeline
+fn get_modeline(text: &Vec<String>) -> Option<String> {
+    let vim = regex::Regex::new(r"\b(?:vim?|ex):.*\b(?:ft|filetype|syntax)=([\w+#.-]+)").unwrap();
+    let emacs = regex::Regex::new(r"-\*-(.*)-\*-").unwrap();
+    let emacs_mode = regex::Regex::new(r"(?:^|;)\s*mode:\s*([^;\s]+)").unwrap();
+
+    let head = text.iter().take(MODELINE_LINES);
+    let tail = text.iter().skip(MODELINE_LINES.max(text.len().saturating_sub(MODELINE_LINES)));
+    for line in head.chain(tail) {
+        match vim.captures(line) {
+            Some(caps) => return Some(caps[1].to_owned()),
+            None => (),
+        }
+        match emacs.captures(line) {
+            Some(caps) => {
+                if !caps[1].contains(':') {
+                    return Some(caps[1].trim().to_owned());
+                }
+                match emacs_mode.captures(&caps[1]) {
+                    Some(mode) => return Some(mode[1].to_owned()),
+                    None => (),
+                }
+            },
+            None => (),
+        }
+    }
+    None
+}
+
+// Breaks a tie between languages claiming the same file: one whose content patterns match
+// wins, then one that doesn't need any, then whichever comes first
+fn pick<'a>(candidates: Vec<&'a Language>, text: &Vec<String>) -> Option<&'a Language> {
+    if candidates.len() <= 1 {
+        return candidates.into_iter().next();
+    }
+
+    for language in &candidates {
+        if text.iter().take(CONTENT_LINES).any(|line| matches_any(&language.detect.content, line)) {
+            return Some(*language);
+        }
+    }
+    match candidates.iter().find(|language| language.detect.content.len() == 0) {
+        Some(language) => Some(*language),
+        None => Some(candidates[0]),
+    }
+}
+
+// Finds a language by id, name or extension, ignoring case
+pub fn find_language(query: &str, errors: &mut Vec<String>) -> Option<String> {
+    find_in(&load_languages(errors), query)
+}
+
+fn find_in(languages: &Vec<Language>, query: &str) -> Option<String> {
+    let query = query.trim().to_lowercase();
+
+    for language in languages {
+        if language.id.to_lowercase() == query || language.name.to_lowercase() == query {
+            return Some(language.id.clone());
+        }
+    }
+    for language in languages {
+        if language.detect.extensions.iter().any(|e| e.to_lowercase() == query) {
+            return Some(language.id.clone());
+        }
+    }
+    None
+}
+
+// Resolves the language of a buffer, in order: modeline, filename glob, longest matching
+// extension, shebang interpreter and first-line pattern
+pub fn detect_language(file_path: &str, text: &Vec<String>, errors: &mut Vec<String>) -> Option<String> {
+    detect_in(&load_languages(errors), file_path, text)
+}
+
+fn detect_in(languages: &Vec<Language>, file_path: &str, text: &Vec<String>) -> Option<String> {
+    match get_modeline(text) {
+        Some(mode) => {
+            match find_in(languages, &mode) {
+                Some(id) => return Some(id),
+                None => (),
+            }
+        },
+        None => (),
+    }
+
+    let file_name: Vec<char> = match Path::new(file_path).file_name() {
+        Some(name) => name.to_string_lossy().chars().collect(),
+        None => Vec::new(),
+    };
+    let file_name_str: String = file_name.iter().collect();
+    let first_line = match text.first() {
+        Some(line) => line.as_str(),
+        None => "",
+    };
+
+    let by_filename = languages.iter().filter(|l| l.detect.filenames.iter().any(|g| glob_match(&g.chars().collect::<Vec<char>>(), &file_name))).collect();
+    match pick(by_filename, text) {
+        Some(language) => return Some(language.id.clone()),
+        None => (),
+    }
+
+    let extension_len = |l: &Language| l.detect.extensions.iter().filter(|e| file_name_str.ends_with(&format![".{}", e])).map(|e| e.len()).max();
+    let longest = languages.iter().filter_map(|l| extension_len(l)).max();
+    if longest.is_some() {
+        let by_extension = languages.iter().filter(|l| extension_len(l) == longest).collect();
+        match pick(by_extension, text) {
+            Some(language) => return Some(language.id.clone()),
+            None => (),
+        }
+    }
+
+    match get_interpreter(first_line) {
+        Some(program) => {
+            let by_interpreter = languages.iter().filter(|l| l.detect.interpreters.iter().any(|i| interpreter_match(i, &program))).collect();
+            match pick(by_interpreter, text) {
+                Some(language) => return Some(language.id.clone()),
+                None => (),
+            }
+        },
+        None => (),
+    }
+
+    let by_first_line = languages.iter().filter(|l| matches_any(&l.detect.first_line, first_line)).collect();
+    match pick(by_first_line, text) {
+        Some(language) => Some(language.id.clone()),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn language(id: &str, detect: &str) -> Language {
+        Language { id: id.to_owned(), name: id.to_uppercase(), detect: serde_json::from_str(detect).unwrap() }
+    }
+
+    fn languages() -> Vec<Language> {
+        vec![
+            language("c", r#"{"extensions": ["c", "h"]}"#),
+            language("cpp", r#"{"extensions": ["cpp", "h"], "content": ["^\\s*class\\b", "\\bstd::"]}"#),
+            language("py", r#"{"filenames": ["SConstruct"], "extensions": ["py"], "interpreters": ["python"]}"#),
+            language("make", r#"{"filenames": ["Makefile", "Makefile.*"], "extensions": ["mk"], "interpreters": ["make"]}"#),
+            language("sh", r#"{"extensions": ["sh"], "interpreters": ["sh", "bash"]}"#),
+            language("docker", r#"{"filenames": ["Dockerfile"], "first_line": ["^FROM\\s+\\S+"]}"#),
+            language("tar", r#"{"extensions": ["gz", "tar.gz"]}"#),
+            language("gz", r#"{"extensions": ["gz"]}"#),
+        ]
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|line| line.to_owned()).collect()
+    }
+
+    fn detect(file_path: &str, text: &str) -> Option<String> {
+        detect_in(&languages(), file_path, &lines(text))
+    }
+
+    fn glob(pattern: &str, name: &str) -> bool {
+        glob_match(&pattern.chars().collect::<Vec<char>>(), &name.chars().collect::<Vec<char>>())
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("Makefile", "Makefile"));
+        assert!(!glob("Makefile", "makefile"));
+        assert!(glob("Makefile.*", "Makefile.am"));
+        assert!(!glob("Makefile.*", "Makefile"));
+        assert!(glob("*", ""));
+        assert!(glob("a*b*c", "aXbYbc"));
+        assert!(!glob("a*b*c", "aXbYb"));
+        assert!(glob("file?.rs", "file1.rs"));
+        assert!(!glob("file?.rs", "file.rs"));
+    }
+
+    #[test]
+    fn interpreters() {
+        assert_eq!(get_interpreter("#!/bin/sh"), Some("sh".to_owned()));
+        assert_eq!(get_interpreter("#! /usr/bin/python3 -u"), Some("python3".to_owned()));
+        assert_eq!(get_interpreter("#!/usr/bin/env python3"), Some("python3".to_owned()));
+        assert_eq!(get_interpreter("#!/usr/bin/env -S python3 -u"), Some("python3".to_owned()));
+        assert_eq!(get_interpreter("#!/usr/bin/env -i PATH=/bin bash"), Some("bash".to_owned()));
+        assert_eq!(get_interpreter("#!/usr/bin/env"), None);
+        assert_eq!(get_interpreter("#!"), None);
+        assert_eq!(get_interpreter("# not a shebang"), None);
+    }
+
+    #[test]
+    fn versioned_interpreters() {
+        assert!(interpreter_match("python", "python"));
+        assert!(interpreter_match("python", "python3"));
+        assert!(interpreter_match("python", "python3.8"));
+        assert!(!interpreter_match("python", "pythonw"));
+        assert!(!interpreter_match("python", "pyth"));
+        assert!(!interpreter_match("sh", "shx"));
+    }
+
+    #[test]
+    fn modelines() {
+        assert_eq!(get_modeline(&lines("// vim: ft=rust")), Some("rust".to_owned()));
+        assert_eq!(get_modeline(&lines("# vim: set ts=4 filetype=python :")), Some("python".to_owned()));
+        assert_eq!(get_modeline(&lines("/* -*- mode: rust -*- */")), Some("rust".to_owned()));
+        assert_eq!(get_modeline(&lines("# -*- coding: utf-8; mode: python -*-")), Some("python".to_owned()));
+        assert_eq!(get_modeline(&lines("// -*- rust -*-")), Some("rust".to_owned()));
+        assert_eq!(get_modeline(&lines("# -*- coding: utf-8 -*-")), None);
+        assert_eq!(get_modeline(&lines("nothing here")), None);
+    }
+
+    #[test]
+    fn modelines_only_near_the_ends() {
+        let mut text = vec!["".to_owned(); 20];
+        text[10] = "// vim: ft=rust".to_owned();
+        assert_eq!(get_modeline(&text), None);
+        text[17] = "// vim: ft=c".to_owned();
+        assert_eq!(get_modeline(&text), Some("c".to_owned()));
+        text[2] = "// vim: ft=cpp".to_owned();
+        assert_eq!(get_modeline(&text), Some("cpp".to_owned()));
+    }
+
+    #[test]
+    fn header_content_tie_break() {
+        // Both claim .h, C++ only when its content patterns match
+        assert_eq!(detect("/p/a.h", "int f(void);"), Some("c".to_owned()));
+        assert_eq!(detect("/p/a.h", "#include <stdio.h>\nclass A {};"), Some("cpp".to_owned()));
+        assert_eq!(detect("/p/a.h", "void f(std::string s);"), Some("cpp".to_owned()));
+        assert_eq!(detect("/p/a.cpp", "int f(void);"), Some("cpp".to_owned()));
+    }
+
+    #[test]
+    fn pick_prefers_languages_without_content_patterns() {
+        let languages = languages();
+        let text = lines("int x;");
+        let (c, cpp) = (&languages[0], &languages[1]);
+        assert_eq!(pick(vec![cpp, c], &text).map(|l| l.id.as_str()), Some("c"));
+        assert_eq!(pick(vec![cpp], &text).map(|l| l.id.as_str()), Some("cpp"));
+        assert!(pick(Vec::new(), &text).is_none());
+    }
+
+    #[test]
+    fn detection_order() {
+        // A modeline beats everything, by id, name or extension
+        assert_eq!(detect("/p/a.c", "# vim: ft=py"), Some("py".to_owned()));
+        assert_eq!(detect("/p/a.c", "# -*- mode: MAKE -*-"), Some("make".to_owned()));
+        assert_eq!(detect("/p/a.c", "# vim: ft=mk"), Some("make".to_owned()));
+        // An unknown modeline is ignored
+        assert_eq!(detect("/p/a.c", "# vim: ft=cobol"), Some("c".to_owned()));
+        // Filenames beat extensions and shebangs
+        assert_eq!(detect("/p/Makefile.py", "#!/bin/sh"), Some("make".to_owned()));
+        assert_eq!(detect("/p/SConstruct", ""), Some("py".to_owned()));
+        // The longest extension wins
+        assert_eq!(detect("/p/a.tar.gz", ""), Some("tar".to_owned()));
+        assert_eq!(detect("/p/a.gz", ""), Some("tar".to_owned()));
+        // Extensions beat shebangs
+        assert_eq!(detect("/p/a.sh", "#!/usr/bin/env python3"), Some("sh".to_owned()));
+        assert_eq!(detect("/p/script", "#!/usr/bin/env -S python3.8 -u"), Some("py".to_owned()));
+        assert_eq!(detect("/p/script", "#!/bin/bash"), Some("sh".to_owned()));
+        // The first line pattern comes last
+        assert_eq!(detect("/p/build", "FROM alpine"), Some("docker".to_owned()));
+        assert_eq!(detect("/p/Dockerfile", "# comment"), Some("docker".to_owned()));
+        assert_eq!(detect("/p/README", "hello"), None);
+        assert_eq!(detect("", ""), None);
+    }
+}
@@ -6,8 +6,30 @@ use ::select;
 use ::undo;
 use ::search;
 use ::syntax;
+use ::indent;
+use ::pairs;
+use ::settings;
 use ::autocomplete;
 use ::config;
+use ::minimap;
+use ::brackets;
+use ::utils;
+
+use std::path::Path;
+
+const MIN_FONT_SIZE: i16 = 6;
+const MAX_FONT_SIZE: i16 = 96;
+const ZOOM_STEP: i16 = 2;
+
+// What the status bar is asking for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Prompt {
+    Language,
+    // Names of the installed themes, read when the prompt opens
+    Theme(Vec<String>),
+    // Index into messages of the one shown
+    Messages(usize),
+}
 
 pub struct Editor<'ttf, 'r> {
     pub text: text::Text<'ttf, 'r>,
@@ -16,6 +38,27 @@ pub struct Editor<'ttf, 'r> {
     pub undo_handler: undo::UndoHandler,
     pub search_handler: search::SearchHandler,
     pub syntax_handler: Option<syntax::SyntaxHandler>,
+    pub bracket_handler: brackets::BracketHandler,
+    pub indent_handler: Option<indent::IndentHandler>,
+    pub pair_handler: Option<pairs::PairHandler>,
+    pub language: Option<String>,
+    pub language_locked: bool,
+    // Read from the language's name file when the language is loaded
+    pub language_name: String,
+    pub settings: settings::LanguageSettings,
+    pub prompt: Option<(Prompt, String)>,
+    pub status_message: Option<String>,
+    // Every error reported so far, oldest first
+    pub messages: Vec<String>,
+    // Points added to the configured font size, kept when the config is reloaded
+    pub zoom: i16,
+    // Set by Alt+M, kept over the config's minimap setting when it is reloaded
+    pub minimap_shown: Option<bool>,
+    // Set by Alt+Z, kept over the language's word_wrap setting when the settings are loaded again
+    pub word_wrap: Option<bool>,
+    // Where the scrollbar thumb was grabbed while it is being dragged
+    pub scrollbar_grab: Option<i32>,
+    pub minimap: minimap::Minimap<'r>,
     pub completion_engine: autocomplete::CompletionEngine,
 
     pub canvas: sdl2::render::Canvas<sdl2::video::Window>,
@@ -28,20 +71,124 @@ impl<'ttf, 'r> Editor<'ttf, 'r> {
         let mut font = ttf_context.load_font(&config.font_path, config.font_size).unwrap();
         font.set_style(sdl2::ttf::FontStyle::NORMAL);
 
+        let mut errors = Vec::new();
+        let fallback_fonts = Self::load_fallback_fonts(ttf_context, config, &mut errors);
+
         let lines: Vec<String> = vec!["".to_owned()];
 
-        Editor{
-            text: text::Text::new(font, lines, config),
+        let mut editor = Editor{
+            text: text::Text::new(font, fallback_fonts, lines, config),
             cursor: cursor::Cursor::new(0, 0, config),
             selected: select::SelectHandler{old_x: 0, old_y: 0, x1: 0, y1: 0, x2: 0, y2: 0},
             undo_handler: undo::UndoHandler::new(),
             search_handler: search::SearchHandler::new(),
             syntax_handler: None,
+            bracket_handler: brackets::BracketHandler::new(),
+            indent_handler: None,
+            pair_handler: None,
+            language: None,
+            language_locked: false,
+            language_name: "?".to_owned(),
+            settings: Default::default(),
+            prompt: None,
+            status_message: None,
+            messages: Vec::new(),
+            zoom: 0,
+            minimap_shown: None,
+            word_wrap: None,
+            scrollbar_grab: None,
+            minimap: minimap::Minimap::new(),
             completion_engine: autocomplete::CompletionEngine::new(),
             canvas: canvas,
             char_timer: 0,
+        };
+        utils::report_errors(&mut editor, &errors);
+        editor
+    }
+
+    // Fonts that aren't installed are skipped, the list names common ones for every system, and
+    // one that fails to load is skipped with an error
+    fn load_fallback_fonts(ttf_context: &'ttf sdl2::ttf::Sdl2TtfContext, config: &config::Config, errors: &mut Vec<String>) -> Vec<sdl2::ttf::Font<'ttf, 'r>> {
+        let mut fonts = Vec::new();
+        for path in config.fallback_fonts.iter().filter(|path| Path::new(path).is_file()) {
+            match ttf_context.load_font(path, config.font_size) {
+                Ok(mut font) => {
+                    font.set_style(sdl2::ttf::FontStyle::NORMAL);
+                    fonts.push(font);
+                },
+                Err(e) => errors.push(format!["{}: {}", path, e]),
+            }
         }
+        fonts
     }
-}
 
+    // Applies a config loaded while running: reloads the font when it changed and resizes
+    // everything measured from it
+    pub fn apply_config(&mut self, ttf_context: &'ttf sdl2::ttf::Sdl2TtfContext, config: &config::Config, old_config: &config::Config) -> Result<(), String> {
+        if config.font_path != old_config.font_path || config.font_size != old_config.font_size || config.fallback_fonts != old_config.fallback_fonts {
+            let mut font = ttf_context.load_font(&config.font_path, config.font_size)?;
+            font.set_style(sdl2::ttf::FontStyle::NORMAL);
+            let mut errors = Vec::new();
+            let fallback_fonts = Self::load_fallback_fonts(ttf_context, config, &mut errors);
+            utils::report_errors(self, &errors);
+
+            self.text.font = font;
+            self.text.fallback_fonts = fallback_fonts;
+            self.text.font_size = config.font_size;
+            self.text.atlas.clear();
+            self.text.astral_fonts.borrow_mut().clear();
+        }
+
+        self.text.tab_width = self.settings.tab_width.unwrap_or(config.tab_width);
+        self.cursor.resize(config);
+        self.text.needs_update = true;
 
+        Ok(())
+    }
+
+    // Width lines are wrapped at, None unless the language settings turn word wrap on
+    pub fn get_wrap_width(&self, config: &config::Config) -> Option<u32> {
+        if self.settings.word_wrap {
+            let (w_width, _) = self.canvas.window().size();
+            Some(w_width.saturating_sub(self.cursor.number_w + self.cursor.side_w + config.cursor_width).max(1))
+        }
+        else {
+            None
+        }
+    }
+
+    // Carries the current zoom and minimap toggle over to a freshly loaded config
+    pub fn apply_overrides(&self, config: &mut config::Config) {
+        config.font_size = (config.font_size as i16 + self.zoom).max(MIN_FONT_SIZE).min(MAX_FONT_SIZE) as u16;
+        match self.minimap_shown {
+            Some(shown) => config.minimap = shown,
+            None => (),
+        }
+    }
+
+    // Ctrl+= and Ctrl+- zoom by one step in `direction`, Ctrl+0 (a direction of 0) goes back
+    // to the configured size
+    pub fn zoom(&mut self, ttf_context: &'ttf sdl2::ttf::Sdl2TtfContext, config: &mut config::Config, direction: i16) -> Result<(), String> {
+        let base = config.font_size as i16 - self.zoom;
+        let zoom = if direction == 0 { 0 } else { self.zoom + direction*ZOOM_STEP };
+        let size = (base + zoom).max(MIN_FONT_SIZE).min(MAX_FONT_SIZE);
+
+        let mut new_config = config.clone();
+        new_config.font_size = size as u16;
+        self.apply_config(ttf_context, &new_config, config)?;
+
+        self.zoom = size - base;
+        *config = new_config;
+
+        // Fewer lines fit when zooming in, the cursor has to stay on screen
+        let rows = self.canvas.window().size().1 / config.font_size as u32;
+        if self.cursor.y + 2 > rows {
+            let shift = (self.cursor.y + 2 - rows).min(self.cursor.y);
+            self.cursor.y -= shift;
+            self.cursor.screen_y += shift;
+        }
+
+        self.status_message = Some(format!["Font size {}", size]);
+        Ok(())
+    }
+}
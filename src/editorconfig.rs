@@ -0,0 +1,479 @@
+extern crate regex;
+
+use ::utils;
+use ::paths;
+use ::settings;
+use ::encoding;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+const EDITORCONFIG_FILE: &str = ".editorconfig";
+
+struct Section {
+    glob: String,
+    properties: Vec<(String, String)>,
+}
+
+struct EditorConfig {
+    dir: String,
+    root: bool,
+    sections: Vec<Section>,
+}
+
+// INI with lowercase keys: "root" before the first section, then [glob] sections
+fn parse_editorconfig(path: &str, dir: &str) -> EditorConfig {
+    let mut config = EditorConfig { dir: dir.to_owned(), root: false, sections: Vec::new() };
+
+    for line in utils::read_file(path).lines() {
+        let line = line.trim();
+        if line == "" || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            config.sections.push(Section { glob: line[1..line.len() - 1].to_owned(), properties: Vec::new() });
+            continue;
+        }
+
+        match line.find(|c| c == '=' || c == ':') {
+            Some(i) => {
+                let key = line[..i].trim().to_lowercase();
+                let value = line[i + 1..].trim().to_owned();
+                match config.sections.last_mut() {
+                    Some(section) => section.properties.push((key, value)),
+                    None => {
+                        if key == "root" {
+                            config.root = value.to_lowercase() == "true";
+                        }
+                    },
+                }
+            },
+            None => (),
+        }
+    }
+    config
+}
+
+// Translates an EditorConfig glob into a regex, numeric ranges ({1..3}) becoming groups
+// whose values are checked after matching
+fn glob_to_regex(glob: &str, ranges: &mut Vec<(i64, i64)>) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut ret = String::new();
+    let mut braces = 0;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                ret.push_str(&regex::escape(&chars[i].to_string()));
+            },
+            '*' => {
+                if chars.get(i + 1) == Some(&'*') {
+                    ret.push_str(".*");
+                    i += 1;
+                }
+                else {
+                    ret.push_str("[^/]*");
+                }
+            },
+            '?' => ret.push_str("[^/]"),
+            '[' => {
+                match chars[i..].iter().position(|&c| c == ']') {
+                    Some(end) if end > 1 => {
+                        let class: String = chars[i + 1..i + end].iter().collect();
+                        let class = if class.starts_with('!') { format!["^{}", &class[1..]] } else { class };
+                        ret.push_str(&format!["[{}]", class.replace("\\", "\\\\").replace("[", "\\[")]);
+                        i += end;
+                    },
+                    _ => ret.push_str("\\["),
+                }
+            },
+            '{' => {
+                let end = chars[i..].iter().position(|&c| c == '}');
+                let inner: String = match end {
+                    Some(end) => chars[i + 1..i + end].iter().collect(),
+                    None => "".to_owned(),
+                };
+                let numbers: Vec<Option<i64>> = inner.splitn(2, "..").map(|n| n.parse().ok()).collect();
+
+                if numbers.len() == 2 && numbers.iter().all(|n| n.is_some()) {
+                    ranges.push((numbers[0].unwrap(), numbers[1].unwrap()));
+                    ret.push_str("([+-]?[0-9]+)");
+                    i += end.unwrap();
+                }
+                else if end.is_some() && inner.contains(',') {
+                    ret.push_str("(?:");
+                    braces += 1;
+                }
+                else {
+                    ret.push_str("\\{");
+                }
+            },
+            ',' if braces > 0 => ret.push('|'),
+            '}' if braces > 0 => {
+                ret.push(')');
+                braces -= 1;
+            },
+            c => ret.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    ret
+}
+
+// Globs without a slash match the file name in any directory below the .editorconfig
+fn glob_matches(glob: &str, dir: &str, file_path: &str) -> bool {
+    let mut ranges = Vec::new();
+    let pattern = if glob.contains('/') {
+        format!["^{}/{}$", regex::escape(dir), glob_to_regex(glob.trim_start_matches('/'), &mut ranges)]
+    }
+    else {
+        format!["^{}/(?:.*/)?{}$", regex::escape(dir), glob_to_regex(glob, &mut ranges)]
+    };
+
+    match regex::Regex::new(&pattern) {
+        Ok(regex) => {
+            match regex.captures(file_path) {
+                Some(caps) => {
+                    ranges.iter().enumerate().all(|(i, &(low, high))| {
+                        match caps.get(i + 1).and_then(|n| n.as_str().parse::<i64>().ok()) {
+                            Some(n) => n >= low.min(high) && n <= low.max(high),
+                            None => false,
+                        }
+                    })
+                },
+                None => false,
+            }
+        },
+        Err(_) => false,
+    }
+}
+
+// Every .editorconfig that could apply to the file, nearest first, up to the first one marked
+// root. Those that don't exist are included so that creating one is noticed
+pub fn get_editorconfig_paths(file_path: &str) -> Vec<String> {
+    let mut ret = Vec::new();
+    if file_path == "" {
+        return ret;
+    }
+
+    let file_path = paths::make_absolute(file_path);
+    for dir in Path::new(&file_path).ancestors().skip(1) {
+        let path = dir.join(EDITORCONFIG_FILE);
+        ret.push(path.display().to_string());
+        if path.is_file() && parse_editorconfig(&path.display().to_string(), "").root {
+            break;
+        }
+    }
+    ret
+}
+
+pub fn is_editorconfig_path(path: &str) -> bool {
+    Path::new(path).file_name().map_or(false, |name| name == EDITORCONFIG_FILE)
+}
+
+// Properties that apply to the file, read from every .editorconfig between the file and the
+// first one marked root, nearer files and later sections winning
+pub fn get_properties(file_path: &str, text_dir: &str) -> HashMap<String, String> {
+    let mut configs = Vec::new();
+    for dir in Path::new(text_dir).ancestors() {
+        let path = dir.join(EDITORCONFIG_FILE);
+        if path.is_file() {
+            let config = parse_editorconfig(&path.display().to_string(), dir.display().to_string().trim_end_matches('/'));
+            let root = config.root;
+            configs.push(config);
+            if root {
+                break;
+            }
+        }
+    }
+
+    let mut properties = HashMap::new();
+    for config in configs.iter().rev() {
+        for section in &config.sections {
+            if glob_matches(&section.glob, &config.dir, file_path) {
+                for (key, value) in &section.properties {
+                    properties.insert(key.clone(), value.to_lowercase());
+                }
+            }
+        }
+    }
+
+    // "unset" removes whatever an outer file set
+    properties.retain(|_, value| value != "unset");
+    properties
+}
+
+fn parse_number(key: &str, value: &str, warnings: &mut Vec<String>) -> Option<usize> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Some(n),
+        _ => {
+            warnings.push(format!["{}: {}: invalid value \"{}\"", EDITORCONFIG_FILE, key, value]);
+            None
+        },
+    }
+}
+
+// Overrides the language settings with the file's EditorConfig properties, returning the
+// values it didn't understand
+pub fn apply_editorconfig(settings: &mut settings::LanguageSettings, file_path: &str, text_dir: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if file_path == "" {
+        return warnings;
+    }
+
+    // Globs are anchored to absolute directories, so "src/main.rs" has to be one too
+    let properties = get_properties(&paths::make_absolute(file_path), &paths::make_absolute(text_dir));
+    let get = |key: &str| properties.get(key).map(|value| value.as_str());
+
+    match get("indent_style") {
+        Some("tab") => settings.use_tabs = true,
+        Some("space") => settings.use_tabs = false,
+        Some(value) => warnings.push(format!["{}: indent_style: invalid value \"{}\"", EDITORCONFIG_FILE, value]),
+        None => (),
+    }
+
+    let tab_width = match get("tab_width") {
+        Some(value) => parse_number("tab_width", value, &mut warnings),
+        None => None,
+    };
+    let indent_size = match get("indent_size") {
+        Some("tab") => tab_width.or(settings.tab_width.map(|w| w as usize)),
+        Some(value) => parse_number("indent_size", value, &mut warnings),
+        // With tabs, one level of indentation is one tab wide
+        None if settings.use_tabs => tab_width,
+        None => None,
+    };
+
+    match indent_size {
+        Some(n) => settings.indent_width = n,
+        None => (),
+    }
+    match tab_width.or(indent_size) {
+        Some(n) => settings.tab_width = Some(n as u32),
+        None => (),
+    }
+
+    match get("end_of_line") {
+        Some("lf") => settings.end_of_line = Some(encoding::LineEnding::Lf),
+        Some("crlf") => settings.end_of_line = Some(encoding::LineEnding::Crlf),
+        Some("cr") => settings.end_of_line = Some(encoding::LineEnding::Cr),
+        Some(value) => warnings.push(format!["{}: end_of_line: invalid value \"{}\"", EDITORCONFIG_FILE, value]),
+        None => (),
+    }
+
+    match get("charset") {
+        Some("utf-8") => settings.charset = Some(encoding::Charset::Utf8),
+        Some("utf-8-bom") => settings.charset = Some(encoding::Charset::Utf8Bom),
+        Some("latin1") => settings.charset = Some(encoding::Charset::Latin1),
+        Some("utf-16be") => settings.charset = Some(encoding::Charset::Utf16Be),
+        Some("utf-16le") => settings.charset = Some(encoding::Charset::Utf16Le),
+        Some(value) => warnings.push(format!["{}: charset: invalid value \"{}\"", EDITORCONFIG_FILE, value]),
+        None => (),
+    }
+
+    match get("trim_trailing_whitespace") {
+        Some("true") => settings.trim_trailing_whitespace = true,
+        Some("false") => settings.trim_trailing_whitespace = false,
+        Some(value) => warnings.push(format!["{}: trim_trailing_whitespace: invalid value \"{}\"", EDITORCONFIG_FILE, value]),
+        None => (),
+    }
+
+    match get("insert_final_newline") {
+        Some("true") => settings.insert_final_newline = Some(true),
+        Some("false") => settings.insert_final_newline = Some(false),
+        Some(value) => warnings.push(format!["{}: insert_final_newline: invalid value \"{}\"", EDITORCONFIG_FILE, value]),
+        None => (),
+    }
+
+    match get("max_line_length") {
+        Some("off") => settings.rulers.clear(),
+        Some(value) => {
+            match parse_number("max_line_length", value, &mut warnings) {
+                Some(n) => settings.rulers = vec![n],
+                None => (),
+            }
+        },
+        None => (),
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    // A fresh directory tree under the temp dir with the given files, returning its path
+    fn make_tree(name: &str, files: &[(&str, &str)]) -> String {
+        let root = env::temp_dir().join(format!["aurum-editorconfig-{}-{}", name, std::process::id()]);
+        let _ = fs::remove_dir_all(&root);
+        for &(path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+        }
+        root.display().to_string()
+    }
+
+    fn matches(glob: &str, file_path: &str) -> bool {
+        glob_matches(glob, "/p", file_path)
+    }
+
+    #[test]
+    fn numeric_ranges() {
+        assert!(matches("file{1..3}.rs", "/p/file1.rs"));
+        assert!(matches("file{1..3}.rs", "/p/file3.rs"));
+        assert!(!matches("file{1..3}.rs", "/p/file4.rs"));
+        assert!(!matches("file{1..3}.rs", "/p/file0.rs"));
+        assert!(matches("file{3..1}.rs", "/p/file2.rs"));
+        assert!(matches("file{-2..2}.rs", "/p/file-1.rs"));
+        assert!(!matches("file{1..3}.rs", "/p/filex.rs"));
+    }
+
+    #[test]
+    fn alternatives() {
+        assert!(matches("*.{rs,toml}", "/p/main.rs"));
+        assert!(matches("*.{rs,toml}", "/p/Cargo.toml"));
+        assert!(!matches("*.{rs,toml}", "/p/README.md"));
+        assert!(matches("{a,b}.txt", "/p/b.txt"));
+        assert!(!matches("{a,b}.txt", "/p/c.txt"));
+        // A single word in braces is taken literally
+        assert!(matches("{a}.txt", "/p/{a}.txt"));
+    }
+
+    #[test]
+    fn stars() {
+        assert!(matches("*.rs", "/p/main.rs"));
+        assert!(matches("*.rs", "/p/src/deep/main.rs"));
+        assert!(!matches("src/*.rs", "/p/src/deep/main.rs"));
+        assert!(matches("src/**.rs", "/p/src/deep/main.rs"));
+        assert!(matches("**/test.rs", "/p/a/b/test.rs"));
+        assert!(!matches("*.rs", "/p/main.rsx"));
+    }
+
+    #[test]
+    fn globs_with_a_slash_are_anchored() {
+        assert!(matches("src/main.rs", "/p/src/main.rs"));
+        assert!(!matches("src/main.rs", "/p/lib/src/main.rs"));
+        assert!(matches("/Makefile", "/p/Makefile"));
+        assert!(!matches("/Makefile", "/p/sub/Makefile"));
+        assert!(matches("Makefile", "/p/sub/Makefile"));
+    }
+
+    #[test]
+    fn nearer_files_and_later_sections_win() {
+        let root = make_tree("precedence", &[
+            (".editorconfig", "root = true\n[*]\nindent_style = space\nindent_size = 4\ncharset = utf-8\n[*.rs]\nindent_size = 2\n"),
+            ("sub/.editorconfig", "[*.rs]\nindent_style = Tab\n[lib.rs]\nindent_size = 8\n"),
+        ]);
+        let dir = format!["{}/sub", root];
+
+        let properties = get_properties(&format!["{}/lib.rs", dir], &dir);
+        assert_eq!(properties.get("indent_style").map(|s| s.as_str()), Some("tab"));
+        assert_eq!(properties.get("indent_size").map(|s| s.as_str()), Some("8"));
+        assert_eq!(properties.get("charset").map(|s| s.as_str()), Some("utf-8"));
+
+        let properties = get_properties(&format!["{}/main.rs", dir], &dir);
+        assert_eq!(properties.get("indent_size").map(|s| s.as_str()), Some("2"));
+
+        let properties = get_properties(&format!["{}/notes.txt", root], &root);
+        assert_eq!(properties.get("indent_style").map(|s| s.as_str()), Some("space"));
+        assert_eq!(properties.get("indent_size").map(|s| s.as_str()), Some("4"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn root_stops_the_search() {
+        let root = make_tree("root", &[
+            (".editorconfig", "[*]\ncharset = latin1\n"),
+            ("sub/.editorconfig", "root = true\n[*]\nindent_size = 3\n"),
+        ]);
+        let dir = format!["{}/sub", root];
+
+        let properties = get_properties(&format!["{}/a.c", dir], &dir);
+        assert_eq!(properties.get("indent_size").map(|s| s.as_str()), Some("3"));
+        assert_eq!(properties.get("charset"), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn unset_removes_outer_properties() {
+        let root = make_tree("unset", &[
+            (".editorconfig", "root = true\n[*]\ntrim_trailing_whitespace = true\nmax_line_length = 80\n"),
+            ("sub/.editorconfig", "[*.md]\ntrim_trailing_whitespace = unset\n"),
+        ]);
+        let dir = format!["{}/sub", root];
+
+        let properties = get_properties(&format!["{}/README.md", dir], &dir);
+        assert_eq!(properties.get("trim_trailing_whitespace"), None);
+        assert_eq!(properties.get("max_line_length").map(|s| s.as_str()), Some("80"));
+
+        let mut settings = settings::LanguageSettings::default();
+        settings.trim_trailing_whitespace = true;
+        let warnings = apply_editorconfig(&mut settings, &format!["{}/README.md", dir], &dir);
+        assert!(warnings.is_empty());
+        // Unset leaves the language settings as they were
+        assert!(settings.trim_trailing_whitespace);
+        assert_eq!(settings.rulers, vec![80]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn invalid_values_are_reported() {
+        let root = make_tree("invalid", &[
+            (".editorconfig", "root = true\n[*]\nindent_style = sideways\nindent_size = 0\nend_of_line = crlf\n"),
+        ]);
+
+        let mut settings = settings::LanguageSettings::default();
+        let warnings = apply_editorconfig(&mut settings, &format!["{}/a.txt", root], &root);
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(settings.end_of_line, Some(encoding::LineEnding::Crlf));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn relative_paths_match_anchored_globs() {
+        let root = make_tree("relative", &[
+            (".editorconfig", "root = true\n[src/*.rs]\nindent_size = 2\n"),
+        ]);
+        // The same file, reached from the working directory
+        let cwd = env::current_dir().unwrap();
+        let up = "../".repeat(cwd.components().count() - 1);
+        let relative = format!["{}{}/./src/main.rs", up, root.trim_start_matches('/')];
+        let relative_dir = format!["{}{}/src", up, root.trim_start_matches('/')];
+
+        let mut settings = settings::LanguageSettings::default();
+        let warnings = apply_editorconfig(&mut settings, &relative, &relative_dir);
+        assert!(warnings.is_empty());
+        assert_eq!(settings.indent_width, 2);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn watched_paths_stop_at_root() {
+        let root = make_tree("watched", &[
+            (".editorconfig", "root = true\n"),
+            ("a/b/.editorconfig", "[*]\nindent_size = 2\n"),
+        ]);
+
+        let paths = get_editorconfig_paths(&format!["{}/a/b/c.rs", root]);
+        // a/ has none yet, but creating one would change the settings
+        assert_eq!(paths, vec![format!["{}/a/b/.editorconfig", root], format!["{}/a/.editorconfig", root], format!["{}/.editorconfig", root]]);
+        assert!(paths.iter().all(|path| is_editorconfig_path(path)));
+        assert!(get_editorconfig_paths("").is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+extern crate serde;
+
+const BOM: &str = "\u{feff}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+}
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    // The first line break decides, files without any count as LF
+    pub fn detect(contents: &str) -> LineEnding {
+        match contents.find(|c| c == '\n' || c == '\r') {
+            Some(i) if contents[i..].starts_with("\r\n") => LineEnding::Crlf,
+            Some(i) if contents[i..].starts_with("\r") => LineEnding::Cr,
+            _ => LineEnding::Lf,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Charset {
+    #[serde(rename = "utf-8")]
+    Utf8,
+    #[serde(rename = "utf-8-bom")]
+    Utf8Bom,
+    #[serde(rename = "latin1")]
+    Latin1,
+    #[serde(rename = "utf-16be")]
+    Utf16Be,
+    #[serde(rename = "utf-16le")]
+    Utf16Le,
+}
+impl Charset {
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Charset::Utf8 | Charset::Utf8Bom => {
+                let contents = String::from_utf8_lossy(bytes);
+                contents.trim_start_matches(BOM).to_owned()
+            },
+            Charset::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+            Charset::Utf16Be | Charset::Utf16Le => {
+                let units: Vec<u16> = bytes.chunks(2).filter(|c| c.len() == 2).map(|c| {
+                    if *self == Charset::Utf16Be { (c[0] as u16) << 8 | c[1] as u16 } else { (c[1] as u16) << 8 | c[0] as u16 }
+                }).collect();
+                let contents = String::from_utf16_lossy(&units);
+                contents.trim_start_matches(BOM).to_owned()
+            },
+        }
+    }
+
+    // Characters latin1 can't hold are written as '?'
+    pub fn encode(&self, contents: &str) -> Vec<u8> {
+        match self {
+            Charset::Utf8 => contents.as_bytes().to_vec(),
+            Charset::Utf8Bom => format!["{}{}", BOM, contents].into_bytes(),
+            Charset::Latin1 => contents.chars().map(|c| if (c as u32) < 256 { c as u8 } else { b'?' }).collect(),
+            Charset::Utf16Be | Charset::Utf16Le => {
+                let mut bytes = Vec::new();
+                for unit in BOM.encode_utf16().chain(contents.encode_utf16()) {
+                    if *self == Charset::Utf16Be {
+                        bytes.push((unit >> 8) as u8);
+                        bytes.push(unit as u8);
+                    }
+                    else {
+                        bytes.push(unit as u8);
+                        bytes.push((unit >> 8) as u8);
+                    }
+                }
+                bytes
+            },
+        }
+    }
+}
+
+pub fn split_lines(contents: &str, line_ending: LineEnding) -> Vec<String> {
+    contents.split(line_ending.as_str()).map(|x| x.to_owned()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "héllo wörld €\r\n\u{1F600} after";
+
+    #[test]
+    fn utf16_round_trips() {
+        for &charset in [Charset::Utf16Le, Charset::Utf16Be].iter() {
+            assert_eq!(charset.decode(&charset.encode(TEXT)), TEXT);
+        }
+    }
+
+    #[test]
+    fn utf16_byte_order() {
+        assert_eq!(Charset::Utf16Le.encode("A"), vec![0xFF, 0xFE, 0x41, 0x00]);
+        assert_eq!(Charset::Utf16Be.encode("A"), vec![0xFE, 0xFF, 0x00, 0x41]);
+        // A character past the basic multilingual plane takes a surrogate pair
+        assert_eq!(Charset::Utf16Be.encode("\u{1F600}"), vec![0xFE, 0xFF, 0xD8, 0x3D, 0xDE, 0x00]);
+    }
+
+    #[test]
+    fn boms_are_stripped() {
+        assert_eq!(Charset::Utf8.decode(b"\xEF\xBB\xBFfn main"), "fn main");
+        assert_eq!(Charset::Utf8Bom.decode(b"\xEF\xBB\xBFfn main"), "fn main");
+        assert_eq!(Charset::Utf16Le.decode(&[0xFF, 0xFE, 0x41, 0x00]), "A");
+        assert_eq!(Charset::Utf16Be.decode(&[0xFE, 0xFF, 0x00, 0x41]), "A");
+        // Files without one decode the same
+        assert_eq!(Charset::Utf16Le.decode(&[0x41, 0x00]), "A");
+        assert_eq!(Charset::Utf8.decode(b"fn main"), "fn main");
+    }
+
+    #[test]
+    fn utf8_bom_is_written_once() {
+        let bytes = Charset::Utf8Bom.encode(TEXT);
+        assert!(bytes.starts_with(b"\xEF\xBB\xBF"));
+        assert_eq!(Charset::Utf8Bom.decode(&bytes), TEXT);
+        assert_eq!(Charset::Utf8.encode(TEXT), TEXT.as_bytes());
+    }
+
+    #[test]
+    fn latin1() {
+        assert_eq!(Charset::Latin1.encode("héllo €"), b"h\xE9llo ?".to_vec());
+        assert_eq!(Charset::Latin1.decode(b"h\xE9llo"), "héllo");
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(LineEnding::detect("a\r\nb\nc"), LineEnding::Crlf);
+        assert_eq!(LineEnding::detect("a\rb"), LineEnding::Cr);
+        assert_eq!(LineEnding::detect("a\nb\r\n"), LineEnding::Lf);
+        assert_eq!(LineEnding::detect("no breaks"), LineEnding::Lf);
+        assert_eq!(split_lines("a\r\nb\r\n", LineEnding::Crlf), vec!["a", "b", ""]);
+    }
+}
@@ -0,0 +1,647 @@
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate plist;
+extern crate regex;
+
+use ::utils;
+use ::syntax;
+
+use serde_json::Value;
+
+use std::collections::HashSet;
+
+// Converts TextMate (.tmLanguage, .tmLanguage.json) and Sublime (.sublime-syntax) grammars into
+// the editor's own rule/region format. Anything that format cannot express is skipped and
+// reported in the returned warnings.
+pub struct GrammarImport {
+    pub syntax: syntax::SyntaxJSON,
+    pub warnings: Vec<String>,
+}
+
+pub const GRAMMAR_FILES: [&str; 3] = ["syntax.sublime-syntax", "syntax.tmLanguage.json", "syntax.tmLanguage"];
+
+pub fn find_grammar_file(dir: &str) -> Option<String> {
+    for name in GRAMMAR_FILES.iter() {
+        let path = format!["{}/{}", dir, name];
+        if ::std::path::Path::new(&path).is_file() {
+            return Some(path);
+        }
+    }
+    None
+}
+
+pub fn import_grammar(path: &str) -> Result<GrammarImport, String> {
+    let mut import = GrammarImport { syntax: syntax::SyntaxJSON::default(), warnings: Vec::new() };
+
+    if path.ends_with(".sublime-syntax") {
+        let file = utils::read_file(path);
+        let decoded: serde_yaml::Value = serde_yaml::from_str(&file).map_err(|e| format!["{}: {}", path, e])?;
+        SublimeImporter::new(&yaml_to_json(decoded), &mut import).import();
+    }
+    else if path.ends_with(".json") {
+        let file = utils::read_file(path);
+        let decoded: Value = serde_json::from_str(&file).map_err(|e| format!["{}: {}", path, e])?;
+        TextMateImporter::new(&decoded, &mut import).import();
+    }
+    else {
+        let decoded: Value = plist::from_file(path).map_err(|e| format!["{}: {}", path, e])?;
+        TextMateImporter::new(&decoded, &mut import).import();
+    }
+
+    Ok(import)
+}
+
+fn yaml_to_json(value: serde_yaml::Value) -> Value {
+    match value {
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => {
+            match n.as_i64() {
+                Some(i) => Value::from(i),
+                None => Value::from(n.as_f64().unwrap_or(0.0)),
+            }
+        },
+        serde_yaml::Value::String(s) => Value::String(s),
+        serde_yaml::Value::Sequence(seq) => Value::Array(seq.into_iter().map(yaml_to_json).collect()),
+        serde_yaml::Value::Mapping(map) => {
+            let mut ret = serde_json::Map::new();
+            for (k, v) in map {
+                let key = match yaml_to_json(k) {
+                    Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                ret.insert(key, yaml_to_json(v));
+            }
+            Value::Object(ret)
+        },
+        _ => Value::Null,
+    }
+}
+
+// Rewrites the Oniguruma escapes the regex crate lacks an equivalent spelling for
+fn convert_regex(pattern: &str) -> String {
+    pattern.replace("\\h", "[0-9a-fA-F]").replace("\\H", "[^0-9a-fA-F]").replace("\\Z", "$")
+}
+
+// First scope of a (possibly space separated) scope list, with the language suffix kept
+fn get_scope(value: Option<&Value>) -> Option<String> {
+    match value.and_then(|v| v.as_str()) {
+        Some(s) => s.split_whitespace().next().map(|s| s.to_owned()),
+        None => None,
+    }
+}
+
+fn is_uniform_scope(scope: &str) -> bool {
+    match syntax::get_scope_root(scope) {
+        "meta" | "source" | "text" | "punctuation" => false,
+        _ => true,
+    }
+}
+
+fn is_escape_pattern(pattern: &str) -> bool {
+    pattern.starts_with("\\\\.") || pattern.starts_with("\\\\(") || pattern.starts_with("\\\\[")
+}
+
+impl GrammarImport {
+    fn warn(&mut self, message: String) {
+        if !self.warnings.contains(&message) {
+            self.warnings.push(message);
+        }
+    }
+
+    fn add_rule(&mut self, scope: &str, pattern: &str, group: usize, context: &str) {
+        let pattern = convert_regex(pattern);
+        match regex::Regex::new(&pattern) {
+            Ok(_) => self.syntax.rules.push(syntax::RuleJSON { scope: scope.to_owned(), pattern: pattern, group: group }),
+            Err(e) => self.warn(format!["{}: unsupported regex for {}: {}", context, scope, e.to_string().lines().last().unwrap_or("")]),
+        }
+    }
+
+    fn add_captures(&mut self, pattern: &str, captures: Option<&Value>, context: &str) {
+        match captures.and_then(|c| c.as_object()) {
+            Some(captures) => {
+                for (group, capture) in captures {
+                    let scope = match capture {
+                        Value::String(_) => get_scope(Some(capture)),
+                        _ => get_scope(capture.get("name")),
+                    };
+                    match (group.parse::<usize>(), scope) {
+                        (Ok(group), Some(scope)) => self.add_rule(&scope, pattern, group, context),
+                        _ => (),
+                    }
+                }
+            },
+            None => (),
+        }
+    }
+
+    fn add_region(&mut self, scope: &str, begin: &str, end: Option<&str>, escape: Option<char>, context: &str) {
+        let begin = convert_regex(begin);
+        let end = end.map(|e| convert_regex(e));
+
+        match regex::Regex::new(&begin) {
+            Ok(_) => (),
+            Err(_) => {
+                self.warn(format!["{}: unsupported begin regex for {}", context, scope]);
+                return;
+            }
+        }
+
+        match &end {
+            Some(end) => {
+                if end.contains("\\2") || end.contains("\\3") {
+                    self.warn(format!["{}: end of {} refers to a capture other than \\1", context, scope]);
+                    return;
+                }
+                match regex::Regex::new(&end.replace("\\1", "")) {
+                    Ok(_) => (),
+                    Err(_) => {
+                        self.warn(format!["{}: unsupported end regex for {}", context, scope]);
+                        return;
+                    }
+                }
+            },
+            None => (),
+        }
+
+        self.syntax.regions.push(syntax::RegionJSON { begin: begin, end: end, scope: scope.to_owned(), escape: escape });
+    }
+}
+
+struct TextMateImporter<'a> {
+    grammar: &'a Value,
+    import: &'a mut GrammarImport,
+    included: HashSet<String>,
+}
+impl<'a> TextMateImporter<'a> {
+    fn new(grammar: &'a Value, import: &'a mut GrammarImport) -> TextMateImporter<'a> {
+        TextMateImporter { grammar: grammar, import: import, included: HashSet::new() }
+    }
+
+    fn import(&mut self) {
+        let grammar = self.grammar;
+        match grammar.get("patterns") {
+            Some(patterns) => self.import_patterns(patterns, "patterns"),
+            None => self.import.warn("grammar has no top-level patterns".to_owned()),
+        }
+    }
+
+    fn import_patterns(&mut self, patterns: &'a Value, context: &str) {
+        match patterns.as_array() {
+            Some(patterns) => {
+                for pattern in patterns {
+                    self.import_pattern(pattern, context);
+                }
+            },
+            None => (),
+        }
+    }
+
+    fn import_pattern(&mut self, pattern: &'a Value, context: &str) {
+        let grammar = self.grammar;
+
+        match pattern.get("include").and_then(|i| i.as_str()) {
+            Some(include) => {
+                if include.starts_with("#") {
+                    let name = &include[1..];
+                    if self.included.insert(name.to_owned()) {
+                        match grammar.get("repository").and_then(|r| r.get(name)) {
+                            Some(rule) => {
+                                let context = format!["repository.{}", name];
+                                if rule.get("match").is_some() || rule.get("begin").is_some() {
+                                    self.import_pattern(rule, &context);
+                                }
+                                else {
+                                    match rule.get("patterns") {
+                                        Some(patterns) => self.import_patterns(patterns, &context),
+                                        None => (),
+                                    }
+                                }
+                            },
+                            None => self.import.warn(format!["{}: include of missing repository entry {}", context, include]),
+                        }
+                    }
+                }
+                else if include != "$self" && include != "$base" {
+                    self.import.warn(format!["{}: include of external grammar {}", context, include]);
+                }
+                return;
+            },
+            None => (),
+        }
+
+        let name = get_scope(pattern.get("name"));
+
+        match pattern.get("match").and_then(|m| m.as_str()) {
+            Some(regex) => {
+                self.import.add_captures(regex, pattern.get("captures"), context);
+                match &name {
+                    Some(name) => self.import.add_rule(name, regex, 0, context),
+                    None => (),
+                }
+                return;
+            },
+            None => (),
+        }
+
+        if pattern.get("while").is_some() {
+            self.import.warn(format!["{}: begin/while rules", context]);
+            return;
+        }
+
+        match (pattern.get("begin").and_then(|b| b.as_str()), pattern.get("end").and_then(|e| e.as_str())) {
+            (Some(begin), Some(end)) => {
+                let scope = name.or(get_scope(pattern.get("contentName")));
+                match scope {
+                    Some(ref scope) if is_uniform_scope(scope) => {
+                        let mut escape = None;
+                        match pattern.get("patterns").and_then(|p| p.as_array()) {
+                            Some(inner) => {
+                                for p in inner {
+                                    match p.get("match").and_then(|m| m.as_str()) {
+                                        Some(m) if is_escape_pattern(m) => escape = Some('\\'),
+                                        _ => self.import.warn(format!["{}: nested patterns inside {}", context, scope]),
+                                    }
+                                }
+                            },
+                            None => (),
+                        }
+                        self.import.add_region(scope, begin, Some(end), escape, context);
+                    },
+                    _ => {
+                        // Structural blocks (meta.*) only group other rules, so their contents apply everywhere
+                        match pattern.get("patterns") {
+                            Some(patterns) => self.import_patterns(patterns, context),
+                            None => (),
+                        }
+                        self.import.add_captures(begin, pattern.get("beginCaptures").or(pattern.get("captures")), context);
+                        self.import.add_captures(end, pattern.get("endCaptures").or(pattern.get("captures")), context);
+                    },
+                }
+            },
+            (Some(_), None) => self.import.warn(format!["{}: begin without end", context]),
+            _ => {
+                match pattern.get("patterns") {
+                    Some(patterns) => self.import_patterns(patterns, context),
+                    None => (),
+                }
+            },
+        }
+    }
+}
+
+struct SublimeImporter<'a> {
+    grammar: &'a Value,
+    import: &'a mut GrammarImport,
+    included: HashSet<String>,
+}
+impl<'a> SublimeImporter<'a> {
+    fn new(grammar: &'a Value, import: &'a mut GrammarImport) -> SublimeImporter<'a> {
+        SublimeImporter { grammar: grammar, import: import, included: HashSet::new() }
+    }
+
+    fn import(&mut self) {
+        let grammar = self.grammar;
+        match grammar.get("contexts") {
+            Some(contexts) => {
+                self.included.insert("prototype".to_owned());
+                self.included.insert("main".to_owned());
+
+                match contexts.get("prototype") {
+                    Some(prototype) => self.import_context(prototype, "prototype"),
+                    None => (),
+                }
+                match contexts.get("main") {
+                    Some(main) => self.import_context(main, "main"),
+                    None => self.import.warn("grammar has no main context".to_owned()),
+                }
+            },
+            None => self.import.warn("grammar has no contexts".to_owned()),
+        }
+    }
+
+    // Expands {{variable}} references, which may themselves refer to other variables
+    fn expand(&self, pattern: &str) -> String {
+        let mut pattern = pattern.to_owned();
+        for _ in 0..10 {
+            if !pattern.contains("{{") {
+                break;
+            }
+            match self.grammar.get("variables").and_then(|v| v.as_object()) {
+                Some(variables) => {
+                    for (name, value) in variables {
+                        match value.as_str() {
+                            Some(value) => pattern = pattern.replace(&format!["{{{{{}}}}}", name], value),
+                            None => (),
+                        }
+                    }
+                },
+                None => break,
+            }
+        }
+        pattern
+    }
+
+    fn get_context(&self, value: &'a Value) -> Option<&'a Value> {
+        let grammar = self.grammar;
+        match value {
+            Value::String(name) => grammar.get("contexts").and_then(|c| c.get(name.as_str())),
+            Value::Array(list) => {
+                // push: [context] and push: [rule, rule] are both valid
+                if list.len() == 1 && list[0].is_string() {
+                    self.get_context(&list[0])
+                }
+                else {
+                    Some(value)
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn get_meta_scope(context: &Value) -> Option<String> {
+        match context.as_array() {
+            Some(rules) => {
+                for rule in rules {
+                    match get_scope(rule.get("meta_scope")).or(get_scope(rule.get("meta_content_scope"))) {
+                        Some(scope) => return Some(scope),
+                        None => (),
+                    }
+                }
+                None
+            },
+            None => None,
+        }
+    }
+
+    fn import_context(&mut self, context: &'a Value, name: &str) {
+        match context.as_array() {
+            Some(rules) => {
+                for rule in rules {
+                    self.import_rule(rule, name);
+                }
+            },
+            None => (),
+        }
+    }
+
+    fn import_rule(&mut self, rule: &'a Value, context: &str) {
+        match rule.get("include").and_then(|i| i.as_str()) {
+            Some(include) => {
+                if self.included.insert(include.to_owned()) {
+                    let grammar = self.grammar;
+                    match grammar.get("contexts").and_then(|c| c.get(include)) {
+                        Some(included) => self.import_context(included, include),
+                        None => self.import.warn(format!["{}: include of external or missing context {}", context, include]),
+                    }
+                }
+                return;
+            },
+            None => (),
+        }
+
+        let pattern = match rule.get("match").and_then(|m| m.as_str()) {
+            Some(pattern) => self.expand(pattern),
+            None => return,
+        };
+
+        for key in ["set", "embed", "branch", "escape"].iter() {
+            if rule.get(*key).is_some() {
+                self.import.warn(format!["{}: '{}' actions", context, key]);
+                return;
+            }
+        }
+
+        match rule.get("push") {
+            Some(push) => {
+                let pushed = match self.get_context(push) {
+                    Some(pushed) => pushed,
+                    None => {
+                        self.import.warn(format!["{}: push of unknown context", context]);
+                        return;
+                    }
+                };
+
+                let scope = match Self::get_meta_scope(pushed).or(get_scope(rule.get("scope"))) {
+                    Some(ref scope) if is_uniform_scope(scope) => scope.clone(),
+                    _ => {
+                        // Structural contexts only group other rules, so their contents apply everywhere
+                        self.import.add_captures(&pattern, rule.get("captures"), context);
+                        let name = match push.as_str() {
+                            Some(name) => name.to_owned(),
+                            None => format!["{}.push", context],
+                        };
+                        if self.included.insert(name.clone()) {
+                            self.import_context(pushed, &name);
+                        }
+                        return;
+                    }
+                };
+
+                let mut end = None;
+                let mut escape = None;
+                match pushed.as_array() {
+                    Some(rules) => {
+                        for inner in rules {
+                            match inner.get("match").and_then(|m| m.as_str()) {
+                                Some(m) => {
+                                    if inner.get("pop").is_some() && end.is_none() {
+                                        end = Some(self.expand(m));
+                                    }
+                                    else if is_escape_pattern(m) {
+                                        escape = Some('\\');
+                                    }
+                                    else {
+                                        self.import.warn(format!["{}: nested rules inside {}", context, scope]);
+                                    }
+                                },
+                                None => (),
+                            }
+                        }
+                    },
+                    None => (),
+                }
+
+                match end {
+                    Some(end) => self.import.add_region(&scope, &pattern, Some(&end), escape, context),
+                    None => self.import.warn(format!["{}: pushed context for {} never pops", context, scope]),
+                }
+            },
+            None => {
+                self.import.add_captures(&pattern, rule.get("captures"), context);
+                match get_scope(rule.get("scope")) {
+                    Some(scope) => self.import.add_rule(&scope, &pattern, 0, context),
+                    None => (),
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    const TM_LANGUAGE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>scopeName</key><string>source.test</string>
+    <key>patterns</key>
+    <array>
+        <dict><key>include</key><string>#keywords</string></dict>
+        <dict><key>include</key><string>#strings</string></dict>
+        <dict><key>include</key><string>source.other</string></dict>
+        <dict>
+            <key>match</key><string>\b(fn)\s+(\w+)</string>
+            <key>captures</key>
+            <dict>
+                <key>1</key><dict><key>name</key><string>keyword.other.test</string></dict>
+                <key>2</key><dict><key>name</key><string>entity.name.function.test</string></dict>
+            </dict>
+        </dict>
+        <dict>
+            <key>begin</key><string>/\*</string>
+            <key>end</key><string>\*/</string>
+            <key>name</key><string>comment.block.test</string>
+        </dict>
+        <dict>
+            <key>begin</key><string>^=</string>
+            <key>while</key><string>^=</string>
+            <key>name</key><string>comment.line.test</string>
+        </dict>
+    </array>
+    <key>repository</key>
+    <dict>
+        <key>keywords</key>
+        <dict>
+            <key>patterns</key>
+            <array>
+                <dict><key>match</key><string>\b(if|else)\b</string><key>name</key><string>keyword.control.test</string></dict>
+                <dict><key>match</key><string>\b\h+h\b</string><key>name</key><string>constant.numeric.test</string></dict>
+                <dict><key>match</key><string>(?&lt;=\.)\w+</string><key>name</key><string>variable.other.member.test</string></dict>
+            </array>
+        </dict>
+        <key>strings</key>
+        <dict>
+            <key>begin</key><string>"</string>
+            <key>end</key><string>"</string>
+            <key>name</key><string>string.quoted.double.test</string>
+            <key>patterns</key>
+            <array><dict><key>match</key><string>\\.</string></dict></array>
+        </dict>
+    </dict>
+</dict>
+</plist>
+"#;
+
+    const SUBLIME_SYNTAX: &str = r#"%YAML 1.2
+---
+name: Test
+scope: source.test
+variables:
+  ident: '[a-z_]+'
+  keyword: '\b(?:let|{{ident}}_kw)\b'
+contexts:
+  prototype:
+    - include: comments
+  main:
+    - match: '{{keyword}}'
+      scope: keyword.other.test
+    - match: '"'
+      push: string
+    - match: '\{'
+      push: block
+    - match: '<'
+      set: generic
+  comments:
+    - match: '#.*$'
+      scope: comment.line.test
+  string:
+    - meta_scope: string.quoted.double.test
+    - match: '\\.'
+      scope: constant.character.escape.test
+    - match: '"'
+      pop: true
+  block:
+    - meta_scope: meta.block.test
+    - match: '\d+'
+      scope: constant.numeric.test
+    - match: '\}'
+      pop: true
+  generic:
+    - match: '>'
+      pop: true
+"#;
+
+    fn import(name: &str, contents: &str) -> GrammarImport {
+        let path = env::temp_dir().join(format!["aurum-grammar-{}-{}", std::process::id(), name]);
+        fs::write(&path, contents).unwrap();
+        let import = import_grammar(&path.display().to_string()).unwrap();
+        let _ = fs::remove_file(&path);
+        import
+    }
+
+    fn rule<'a>(import: &'a GrammarImport, scope: &str) -> Option<&'a syntax::RuleJSON> {
+        import.syntax.rules.iter().find(|r| r.scope == scope)
+    }
+
+    fn region<'a>(import: &'a GrammarImport, scope: &str) -> Option<&'a syntax::RegionJSON> {
+        import.syntax.regions.iter().find(|r| r.scope == scope)
+    }
+
+    #[test]
+    fn tm_language() {
+        let import = import("syntax.tmLanguage", TM_LANGUAGE);
+
+        assert_eq!(rule(&import, "keyword.control.test").unwrap().pattern, "\\b(if|else)\\b");
+        assert_eq!(rule(&import, "constant.numeric.test").unwrap().pattern, "\\b[0-9a-fA-F]+h\\b");
+        assert!(rule(&import, "variable.other.member.test").is_none());
+
+        let name = rule(&import, "entity.name.function.test").unwrap();
+        assert_eq!((name.pattern.as_str(), name.group), ("\\b(fn)\\s+(\\w+)", 2));
+        assert_eq!(rule(&import, "keyword.other.test").unwrap().group, 1);
+
+        let string = region(&import, "string.quoted.double.test").unwrap();
+        assert_eq!((string.begin.as_str(), string.end.as_ref().map(|e| e.as_str()), string.escape), ("\"", Some("\""), Some('\\')));
+        assert_eq!(region(&import, "comment.block.test").unwrap().escape, None);
+        assert!(region(&import, "comment.line.test").is_none());
+
+        assert!(import.warnings.contains(&"patterns: include of external grammar source.other".to_owned()));
+        assert!(import.warnings.contains(&"patterns: begin/while rules".to_owned()));
+        assert!(import.warnings.iter().any(|w| w.starts_with("repository.keywords: unsupported regex for variable.other.member.test")));
+        assert_eq!(import.warnings.len(), 3);
+    }
+
+    #[test]
+    fn tm_language_json() {
+        let import = import("syntax.tmLanguage.json", r##"{
+            "patterns": [
+                {"match": "\\b\\d+\\b", "name": "constant.numeric.test"},
+                {"include": "#missing"}
+            ]
+        }"##);
+
+        assert_eq!(import.syntax.rules.len(), 1);
+        assert_eq!(import.warnings, vec!["patterns: include of missing repository entry #missing".to_owned()]);
+    }
+
+    #[test]
+    fn sublime_syntax() {
+        let import = import("syntax.sublime-syntax", SUBLIME_SYNTAX);
+
+        // Variables expand inside other variables
+        assert_eq!(rule(&import, "keyword.other.test").unwrap().pattern, "\\b(?:let|[a-z_]+_kw)\\b");
+        assert_eq!(rule(&import, "comment.line.test").unwrap().pattern, "#.*$");
+        // The contents of a structural context apply everywhere
+        assert_eq!(rule(&import, "constant.numeric.test").unwrap().pattern, "\\d+");
+        assert!(region(&import, "meta.block.test").is_none());
+
+        let string = region(&import, "string.quoted.double.test").unwrap();
+        assert_eq!((string.begin.as_str(), string.end.as_ref().map(|e| e.as_str()), string.escape), ("\"", Some("\""), Some('\\')));
+
+        assert_eq!(import.warnings, vec!["main: 'set' actions".to_owned()]);
+    }
+}
@@ -0,0 +1,183 @@
+extern crate serde;
+extern crate serde_json;
+extern crate regex;
+
+use ::utils;
+use ::settings;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndentJSON {
+    increase: String,
+    decrease: String,
+    #[serde(default)]
+    indent_next: Option<String>,
+    electric: String,
+}
+
+#[derive(Debug)]
+pub struct IndentHandler {
+    pub increase: regex::Regex,
+    pub decrease: regex::Regex,
+    // Lines that indent only the line after them, like an if without braces
+    pub indent_next: Option<regex::Regex>,
+    pub electric: String,
+}
+impl IndentHandler {
+    pub fn parse_indent_file(path: &str) -> Result<IndentHandler, Vec<String>> {
+        let file = utils::read_file(path);
+
+        let decoded: Result<IndentJSON, serde_json::Error> = serde_json::from_str(&file);
+
+        match decoded {
+            Ok(decoded) => {
+                let mut errors = Vec::new();
+                let mut compile = |field: &str, pattern: &str| {
+                    match regex::Regex::new(pattern) {
+                        Ok(regex) => Some(regex),
+                        Err(e) => {
+                            errors.push(format!["{}: {}", field, e]);
+                            None
+                        }
+                    }
+                };
+
+                let increase = compile("increase", &decoded.increase);
+                let decrease = compile("decrease", &decoded.decrease);
+                let indent_next = match decoded.indent_next {
+                    Some(ref pattern) => compile("indent_next", pattern).map(Some),
+                    None => Some(None),
+                };
+
+                match (increase, decrease, indent_next) {
+                    (Some(increase), Some(decrease), Some(indent_next)) => {
+                        return Ok(IndentHandler {
+                            increase: increase,
+                            decrease: decrease,
+                            indent_next: indent_next,
+                            electric: decoded.electric,
+                        })
+                    },
+                    _ => return Err(errors),
+                }
+            },
+            Err(e) => {
+                return Err(vec![e.to_string()]);
+            }
+        }
+    }
+
+    // Columns a tab stands for, the buffer's tab width, which the settings are given from the
+    // config when nothing else sets it
+    fn get_tab_width(settings: &settings::LanguageSettings) -> usize {
+        settings.tab_width.map(|w| w as usize).unwrap_or(settings.indent_width).max(1)
+    }
+
+    // Columns of leading whitespace, a tab advancing to the next tab stop
+    pub fn get_indent_width(line: &str, settings: &settings::LanguageSettings) -> usize {
+        let tab_width = Self::get_tab_width(settings);
+        let whitespace = &line[..line.len() - line.trim_start().len()];
+        whitespace.chars().fold(0, |x, c| if c == '\t' { (x/tab_width + 1)*tab_width } else { x + 1 })
+    }
+
+    fn indents_next(&self, line: &str) -> bool {
+        match self.indent_next {
+            Some(ref indent_next) => indent_next.is_match(line),
+            None => false,
+        }
+    }
+
+    fn previous_line(text: &Vec<String>, y: usize) -> Option<usize> {
+        let mut i = y;
+        while i > 0 {
+            i -= 1;
+            if text[i].trim().len() > 0 {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    // Indentation the line after `line` should start with, ignoring whatever that line contains
+    pub fn get_next_indent(&self, text: &Vec<String>, y: usize, settings: &settings::LanguageSettings) -> usize {
+        let line = &text[y];
+        let mut indent = Self::get_indent_width(line, settings);
+
+        if self.increase.is_match(line) || self.indents_next(line) {
+            indent += settings.indent_width;
+        }
+        else {
+            match Self::previous_line(text, y) {
+                Some(p) => {
+                    if self.indents_next(&text[p]) && !self.increase.is_match(&text[p]) {
+                        indent = indent.saturating_sub(settings.indent_width);
+                    }
+                },
+                None => (),
+            }
+        }
+
+        indent
+    }
+
+    pub fn get_line_indent(&self, text: &Vec<String>, y: usize, settings: &settings::LanguageSettings) -> usize {
+        let mut indent = match Self::previous_line(text, y) {
+            Some(p) => self.get_next_indent(text, p, settings),
+            None => 0,
+        };
+
+        if self.decrease.is_match(&text[y]) {
+            indent = indent.saturating_sub(settings.indent_width);
+        }
+
+        indent
+    }
+
+    // Replaces the leading whitespace of a line, returning how many bytes were added (or removed)
+    // With tabs, whatever doesn't fill a whole level is padded with spaces
+    pub fn set_line_indent(text: &mut Vec<String>, y: usize, indent: usize, settings: &settings::LanguageSettings) -> isize {
+        let old_len = text[y].len();
+
+        let mut new_line = if settings.use_tabs {
+            let tab_width = Self::get_tab_width(settings);
+            let mut whitespace = "\t".repeat(indent / tab_width);
+            whitespace.push_str(&" ".repeat(indent % tab_width));
+            whitespace
+        }
+        else {
+            " ".repeat(indent)
+        };
+        new_line.push_str(text[y].trim_start());
+        text[y] = new_line;
+
+        text[y].len() as isize - old_len as isize
+    }
+
+    pub fn reindent_line(&self, text: &mut Vec<String>, y: usize, settings: &settings::LanguageSettings) -> isize {
+        if text[y].trim().len() == 0 {
+            return 0;
+        }
+
+        let indent = self.get_line_indent(text, y, settings);
+        Self::set_line_indent(text, y, indent, settings)
+    }
+
+    pub fn reindent_lines(&self, text: &mut Vec<String>, y1: usize, y2: usize, settings: &settings::LanguageSettings) {
+        for y in y1..=y2 {
+            self.reindent_line(text, y, settings);
+        }
+    }
+
+    pub fn is_electric(&self, input: &str) -> bool {
+        input.chars().any(|c| self.electric.contains(c))
+    }
+}
+
+pub fn get_default_next_indent(line: &str, settings: &settings::LanguageSettings) -> usize {
+    let mut indent = IndentHandler::get_indent_width(line, settings);
+
+    if line.trim().ends_with("{") || line.trim().ends_with(":") || line.trim().ends_with("(") {
+        indent += settings.indent_width;
+    }
+
+    indent
+}
@@ -0,0 +1,140 @@
+use ::text;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+// One row on screen: a whole line, or a piece of one when wrapping
+pub struct Row {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// Where the lines from the top of the screen down are drawn, row by row
+pub struct Layout {
+    pub rows: Vec<Row>,
+}
+impl Layout {
+    pub fn new(text: &text::Text, screen_y: usize, row_count: usize, wrap_width: Option<u32>) -> Layout {
+        let mut rows = Vec::new();
+        let mut y = screen_y;
+        while rows.len() < row_count && y < text.raw.len() {
+            let line = &text.raw[y];
+            let starts = get_row_starts(text, line, wrap_width);
+            for (i, &start) in starts.iter().enumerate() {
+                if rows.len() == row_count {
+                    break;
+                }
+                let end = starts.get(i + 1).cloned().unwrap_or(line.len());
+                rows.push(Row { line: y, start: start, end: end });
+            }
+            y += 1;
+        }
+        Layout { rows: rows }
+    }
+
+    // One past the last line with a row on screen
+    pub fn get_line_limit(&self) -> usize {
+        self.rows.last().map_or(0, |row| row.line + 1)
+    }
+
+    // Row and x offset the position is drawn at, None when it is off screen
+    pub fn get_position(&self, text: &text::Text, y: usize, x: usize) -> Option<(usize, u32)> {
+        let line = &text.raw[y];
+        for (i, row) in self.rows.iter().enumerate() {
+            // A position at a wrap belongs to the row starting there
+            if row.line == y && row.start <= x && (x < row.end || row.end == line.len()) {
+                return Some((i, text.get_text_width(&line[row.start..x])));
+            }
+        }
+        None
+    }
+
+    // Rows and horizontal extents covering bytes x1..x2 of line y
+    pub fn get_spans(&self, text: &text::Text, y: usize, x1: usize, x2: usize) -> Vec<(usize, u32, u32)> {
+        let line = &text.raw[y];
+        let mut spans = Vec::new();
+        for (i, row) in self.rows.iter().enumerate() {
+            if row.line != y || x2 < row.start || x1 > row.end {
+                continue;
+            }
+            let start = x1.max(row.start);
+            let end = x2.min(row.end);
+            if start < end {
+                let left = text.get_text_width(&line[row.start..start]);
+                let right = text.get_text_width(&line[row.start..end]);
+                spans.push((i, left, right - left));
+            }
+        }
+        spans
+    }
+}
+
+// Pixel offset after drawing `character` at x, tabs going to the next stop
+fn advance(text: &text::Text, x: u32, character: &str) -> u32 {
+    if character == "\t" {
+        text.get_tab_stop(x)
+    }
+    else {
+        x + text.get_text_width(character)
+    }
+}
+
+// Byte offsets the rows of a line start at, breaking after whitespace where a word would cross
+// `wrap_width` and inside words longer than a row. Whitespace hangs past the edge rather than
+// starting a row
+pub fn get_row_starts(text: &text::Text, line: &str, wrap_width: Option<u32>) -> Vec<usize> {
+    let mut starts = vec![0];
+    let width = match wrap_width {
+        Some(width) => width,
+        None => return starts,
+    };
+
+    let mut x = 0;
+    let mut break_at = None;
+    for (i, c) in line.grapheme_indices(true) {
+        let space = c.trim().is_empty();
+        let mut next = advance(text, x, c);
+
+        if next > width && !space && i > *starts.last().unwrap() {
+            let start = break_at.unwrap_or(i);
+            starts.push(start);
+            break_at = None;
+
+            x = text.get_text_width(&line[start..i]);
+            next = advance(text, x, c);
+            if next > width && i > start {
+                starts.push(i);
+                next = advance(text, 0, c);
+            }
+        }
+
+        x = next;
+        if space {
+            break_at = Some(i + c.len());
+        }
+    }
+    starts
+}
+
+// Byte offset in the row start..end of the line closest to `x` pixels from the row's start
+pub fn get_x_at(text: &text::Text, line: &str, start: usize, end: usize, x: i32) -> usize {
+    let mut width = 0;
+    let mut len = start;
+    let mut last = 0;
+    for c in line[start..end].graphemes(true) {
+        if (x - width as i32) < text.font_size as i32/2 {
+            return len;
+        }
+        width = advance(text, width, c);
+        len += c.len();
+        last = c.len();
+    }
+
+    // The end of a wrapped row is the start of the next one
+    if end < line.len() {
+        len - last
+    }
+    else {
+        len
+    }
+}
@@ -2,32 +2,110 @@ extern crate sdl2;
 extern crate unicode_segmentation;
 extern crate nfd;
 #[macro_use] extern crate serde_derive;
+extern crate serde_json;
 
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 
 use unicode_segmentation::UnicodeSegmentation;
 
-use std::env;
-
 macro_rules! rect(($x:expr, $y:expr, $w:expr, $h:expr) => (sdl2::rect::Rect::new($x as i32, $y as i32, $w as u32, $h as u32)));
 macro_rules! color(($a:expr) => (Color::RGB($a[0] as u8, $a[1] as u8, $a[2] as u8)));
 macro_rules! color_a(($a:expr, $alpha:expr) => (Color::RGBA($a[0] as u8, $a[1] as u8, $a[2] as u8, $alpha)));
 
-const WHITE: sdl2::pixels::Color = sdl2::pixels::Color {r: 255, g: 255, b: 255, a: 255};
-
 mod utils;
+mod paths;
 mod text;
+mod atlas;
+mod layout;
+mod scrollbar;
+mod minimap;
 mod cursor;
 mod select;
 mod undo;
 mod search;
 mod editor;
 mod syntax;
+mod indent;
+mod pairs;
+mod settings;
+mod comment;
+mod encoding;
+mod editorconfig;
+mod brackets;
+mod grammar;
+mod detect;
+mod check;
+mod bench;
+mod theme;
+mod watch;
 mod autocomplete;
 mod config;
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // --config <path> replaces the user's config.json and may be combined with any other mode
+    let explicit_config = match args.iter().position(|arg| arg == "--config") {
+        Some(i) if i + 1 < args.len() => {
+            let path = args.remove(i + 1);
+            args.remove(i);
+            Some(path)
+        },
+        _ => None,
+    };
+    let mut config_layers = paths::get_config_layers(explicit_config.as_ref().map(|path| path.as_str()), "");
+
+    if args.len() == 2 && args[1] == "--print-config" {
+        for path in &config_layers {
+            eprintln!["{}", path];
+        }
+        match config::merge_layers(&config_layers) {
+            Ok((merged, warnings)) => {
+                for warning in warnings {
+                    eprintln!["{}", warning];
+                }
+                println!["{}", serde_json::to_string_pretty(&merged).unwrap()];
+            },
+            Err(e) => {
+                eprintln!["{}", e];
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if args.len() == 3 && args[1] == "--import-grammar" {
+        match grammar::import_grammar(&args[2]) {
+            Ok(import) => {
+                for warning in &import.warnings {
+                    eprintln!["skipped {}", warning];
+                }
+                println!["{}", serde_json::to_string_pretty(&import.syntax).unwrap()];
+            },
+            Err(e) => {
+                eprintln!["{}", e];
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if args.len() == 3 && args[1] == "--import-theme" {
+        match theme::parse_theme_file(&args[2]) {
+            Ok(theme) => println!["{}", serde_json::to_string_pretty(&theme).unwrap()],
+            Err(e) => {
+                eprintln!["{}", e];
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if args.len() == 3 && args[1] == "--check-syntax" {
+        if !check::check_langs(&args[2]) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let ttf_context = sdl2::ttf::init().unwrap();
@@ -40,15 +118,51 @@ fn main() {
         .build()
         .unwrap();
 
-    let canvas = window.into_canvas().build().unwrap();
+    // The software renderer makes `--bench` numbers comparable between machines, --per-glyph
+    // draws without the atlas to compare with
+    let benching = args.len() >= 3 && args[1] == "--bench";
+    let software = benching && args[3..].iter().any(|arg| arg == "--software");
+    let per_glyph = benching && args[3..].iter().any(|arg| arg == "--per-glyph");
+    let canvas = if software {
+        window.into_canvas().software().build().unwrap()
+    }
+    else {
+        window.into_canvas().build().unwrap()
+    };
     let texture_creator = canvas.texture_creator();
 
-    let config = config::Config::load_config("config.json");
+    let mut config = config::Config::load_config(&config_layers);
 
     let mut editor = editor::Editor::create(canvas, &ttf_context, &config);
+    utils::report_errors(&mut editor, &config.warnings);
+
+    let mut bench = None;
+    if benching {
+        editor.text.atlas.per_glyph = per_glyph;
+        utils::load_buffer(&mut editor, &args[2], &config);
+
+        bench = Some(bench::Bench::new());
+    }
+
+    let mut watcher = watch::FileWatcher::new();
+    utils::watch_config_files(&mut watcher, &config_layers, &config, &editor.text.file_path);
+    let mut last_watch = std::time::Instant::now();
+    // File the project configs in config_layers were found for
+    let mut layers_file_path = "".to_owned();
+
+    // Set when the cursor may have moved, so the next frame scrolls sideways to it
+    let mut follow_cursor = false;
 
     'running: loop {
         for event in event_pump.poll_iter() {
+            match event {
+                Event::KeyDown { .. } | Event::TextInput { .. } => {
+                    editor.status_message = None;
+                    follow_cursor = true;
+                },
+                _ => (),
+            }
+
             match event {
                 Event::Quit {..} => {
                     break 'running
@@ -67,20 +181,54 @@ fn main() {
                 },
 
                 Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
-                    editor.cursor.up(&editor.text.raw, &editor.canvas, &config);
+                    match editor.prompt {
+                        Some((editor::Prompt::Messages(i), _)) => {
+                            editor.prompt = Some((editor::Prompt::Messages(i.saturating_sub(1)), "".to_owned()));
+                            editor.text.needs_update = true;
+                            continue;
+                        },
+                        _ => (),
+                    }
+                    match editor.get_wrap_width(&config) {
+                        Some(width) => editor.cursor.up_row(&editor.text, &editor.canvas, &config, Some(width)),
+                        None => editor.cursor.up(&editor.text.raw, &editor.canvas, &config),
+                    }
                     editor.completion_engine.list_mode = false;
                     editor.text.needs_update = true;
                 },
 
                 Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
-                    editor.cursor.down(&editor.text.raw, &editor.canvas, &config);
+                    match editor.prompt {
+                        Some((editor::Prompt::Messages(i), _)) => {
+                            let last = editor.messages.len().saturating_sub(1);
+                            editor.prompt = Some((editor::Prompt::Messages((i + 1).min(last)), "".to_owned()));
+                            editor.text.needs_update = true;
+                            continue;
+                        },
+                        _ => (),
+                    }
+                    match editor.get_wrap_width(&config) {
+                        Some(width) => editor.cursor.down_row(&editor.text, &editor.canvas, &config, Some(width)),
+                        None => editor.cursor.down(&editor.text.raw, &editor.canvas, &config),
+                    }
                     editor.completion_engine.list_mode = false;
                     editor.text.needs_update = true;
                 },
 
                 Event::KeyDown { keycode: Some(Keycode::N), keymod, .. } => {
                     if keymod.contains(sdl2::keyboard::Mod::LCTRLMOD) {
+                        let (zoom, minimap_shown, word_wrap) = (editor.zoom, editor.minimap_shown, editor.word_wrap);
+                        let mut messages = editor.messages;
                         editor = editor::Editor::create(editor.canvas, &ttf_context, &config);
+                        editor.zoom = zoom;
+                        editor.minimap_shown = minimap_shown;
+                        editor.word_wrap = word_wrap;
+                        match word_wrap {
+                            Some(word_wrap) => editor.settings.word_wrap = word_wrap,
+                            None => (),
+                        }
+                        messages.append(&mut editor.messages);
+                        editor.messages = messages;
                     }
                 },
 
@@ -90,26 +238,20 @@ fn main() {
                         let result = nfd::open_file_dialog(None, Some(&dir)).unwrap();
                         match result {
                             nfd::Response::Okay(file_path) => {
-                                editor.text.raw = utils::read_file(&file_path).split("\n").map(|x| x.to_owned()).collect();
-                                editor.text.file_path = file_path;
+                                if !utils::load_buffer(&mut editor, &file_path, &config) {
+                                    editor.text.needs_update = true;
+                                    continue;
+                                }
 
                                 editor.cursor.x = 0;
                                 editor.cursor.wanted_x = 0;
+                                editor.cursor.screen_x = 0;
                                 editor.cursor.screen_y = 0;
                                 editor.cursor.y = 0;
 
                                 editor.undo_handler.clear_states();
                                 utils::update_timer(&mut editor);
 
-                                let text_type = editor.text.get_text_type();
-                                if text_type != "?" {
-                                    let path = format!["{}/langs/{}/syntax.json", env::current_dir().unwrap().display(), text_type];
-                                    editor.syntax_handler = syntax::SyntaxHandler::parse_syntax_file(&path);
-                                }
-                                else {
-                                    editor.syntax_handler = None;
-                                }
-
                                 editor.text.needs_update = true;
                             },
 
@@ -121,23 +263,17 @@ fn main() {
                 Event::KeyDown { keycode: Some(Keycode::S), keymod, .. } => {
                     if keymod.contains(sdl2::keyboard::Mod::LCTRLMOD) {
                         if editor.text.file_path != "" {
-                            utils::save_file(&editor.text.file_path, &editor.text.raw);
+                            let file_path = editor.text.file_path.clone();
+                            utils::save_file(&mut editor, &file_path);
                         }
                         else {
                             let result = nfd::open_save_dialog(None, None).unwrap();
                             match result {
                                 nfd::Response::Okay(file_path) => {
-                                    utils::save_file(&file_path, &editor.text.raw);
-                                    editor.text.file_path = file_path;
+                                    editor.text.file_path = file_path.clone();
+                                    utils::load_language(&mut editor, &config);
 
-                                    let text_type = editor.text.get_text_type();
-                                    if text_type != "?" {
-                                        let path = format!["{}/langs/{}/syntax.json", env::current_dir().unwrap().display(), text_type];
-                                        editor.syntax_handler = syntax::SyntaxHandler::parse_syntax_file(&path);
-                                    }
-                                    else {
-                                        editor.syntax_handler = None;
-                                    }
+                                    utils::save_file(&mut editor, &file_path);
 
                                     editor.text.needs_update = true;
                                 },
@@ -160,6 +296,13 @@ fn main() {
                         editor.undo_handler.restore_previous_state(&mut editor.cursor, &mut editor.text);
                         editor.text.needs_update = true;
                     }
+                    else if keymod.contains(sdl2::keyboard::Mod::LALTMOD) {
+                        editor.settings.word_wrap = !editor.settings.word_wrap;
+                        editor.word_wrap = Some(editor.settings.word_wrap);
+                        editor.cursor.screen_x = 0;
+                        editor.status_message = Some(format!["Word wrap {}", if editor.settings.word_wrap { "on" } else { "off" }]);
+                        editor.text.needs_update = true;
+                    }
                 },
 
 
@@ -176,7 +319,9 @@ fn main() {
                             if editor.completion_engine.completion_list.len() == 1 {
                                 let complete = &editor.completion_engine.completion_list[0][editor.completion_engine.cur_word.len()..];
 
-                                editor.text.raw[editor.cursor.get_absolute_y()].insert_str(editor.cursor.x as usize, &complete);
+                                let y = editor.cursor.get_absolute_y();
+                                editor.text.raw[y].insert_str(editor.cursor.x as usize, &complete);
+                                editor.text.mark_dirty(y, y + 1);
                                 editor.cursor.x += complete.len() as u32;
                             }
                             else {
@@ -189,8 +334,155 @@ fn main() {
                     }
                 },
 
+                Event::KeyDown { keycode: Some(Keycode::I), keymod, .. } => {
+                    if keymod.contains(sdl2::keyboard::Mod::LCTRLMOD) {
+                        if !editor.search_handler.active {
+                            match &editor.indent_handler {
+                                Some(indent_handler) => {
+                                    let (y1, y2) =
+                                        if editor.selected.x1 != editor.selected.x2 || editor.selected.y1 != editor.selected.y2 {
+                                            (editor.selected.y1, editor.selected.y2)
+                                        }
+                                        else {
+                                            (editor.cursor.get_absolute_y(), editor.cursor.get_absolute_y())
+                                        };
+
+                                    indent_handler.reindent_lines(&mut editor.text.raw, y1, y2, &editor.settings);
+                                    editor.text.mark_dirty(y1, y2 + 1);
+
+                                    let len = editor.text.raw[editor.cursor.get_absolute_y()].len() as u32;
+                                    if editor.cursor.x > len {
+                                        editor.cursor.x = len;
+                                    }
+                                    editor.cursor.wanted_x = editor.cursor.x;
+
+                                    editor.selected.reset_selection();
+                                    utils::update_timer(&mut editor);
+                                },
+                                None => (),
+                            }
+                            editor.text.needs_update = true;
+                        }
+                    }
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::Slash), keymod, .. } => {
+                    if keymod.contains(sdl2::keyboard::Mod::LCTRLMOD) {
+                        if !editor.search_handler.active && editor.prompt.is_none() {
+                            // A selection the cursor has since moved away from is ignored, the cursor's
+                            // line is toggled instead
+                            let y = editor.cursor.get_absolute_y();
+                            let selected = editor.selected.x1 != editor.selected.x2 || editor.selected.y1 != editor.selected.y2;
+                            let (y1, y2) =
+                                if selected && editor.selected.y1 <= y && y <= editor.selected.y2 {
+                                    (editor.selected.y1, editor.selected.y2)
+                                }
+                                else {
+                                    (y, y)
+                                };
+
+                            match comment::toggle_comment(&mut editor.text.raw, y1, y2, &editor.settings) {
+                                Some(diffs) => {
+                                    editor.text.mark_dirty(y1, y2 + 1);
+                                    let x = editor.cursor.x as isize + diffs[y - y1];
+                                    editor.cursor.x = x.max(0).min(editor.text.raw[y].len() as isize) as u32;
+                                    editor.cursor.wanted_x = editor.cursor.x;
+
+                                    editor.selected.reset_selection();
+                                    utils::update_timer(&mut editor);
+                                    editor.text.needs_update = true;
+                                },
+                                None => {
+                                    editor.status_message = Some(format!["No comment tokens set for {}", editor.language_name]);
+                                    editor.text.needs_update = true;
+                                },
+                            }
+                        }
+                    }
+                },
+
+                Event::KeyDown { keycode: Some(key @ Keycode::Equals), keymod, .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::KpPlus), keymod, .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Minus), keymod, .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::KpMinus), keymod, .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Num0), keymod, .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Kp0), keymod, .. } => {
+                    if keymod.contains(sdl2::keyboard::Mod::LCTRLMOD) {
+                        let direction = match key {
+                            Keycode::Equals | Keycode::KpPlus => 1,
+                            Keycode::Minus | Keycode::KpMinus => -1,
+                            _ => 0,
+                        };
+
+                        match editor.zoom(&ttf_context, &mut config, direction) {
+                            Ok(_) => (),
+                            Err(e) => utils::report_errors(&mut editor, &vec![e]),
+                        }
+                        editor.completion_engine.list_mode = false;
+                    }
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::M), keymod, .. } => {
+                    if keymod.contains(sdl2::keyboard::Mod::LCTRLMOD) {
+                        utils::update_syntax(&mut editor);
+
+                        match brackets::find_match(&editor.text.raw, &editor.syntax_handler, editor.cursor.x as usize, editor.cursor.get_absolute_y(), 0..editor.text.raw.len()) {
+                            Some((_, (x, y))) => {
+                                editor.cursor.go_to(x as u32, y, &editor.text.raw, &editor.canvas, &config);
+                                editor.completion_engine.list_mode = false;
+                                editor.text.needs_update = true;
+                            },
+                            None => (),
+                        }
+                    }
+                    else if keymod.contains(sdl2::keyboard::Mod::LALTMOD) {
+                        config.minimap = !config.minimap;
+                        editor.minimap_shown = Some(config.minimap);
+                        editor.status_message = Some(format!["Minimap {}", if config.minimap { "on" } else { "off" }]);
+                        editor.text.needs_update = true;
+                    }
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::L), keymod, .. } => {
+                    if keymod.contains(sdl2::keyboard::Mod::LCTRLMOD) {
+                        if !editor.search_handler.active {
+                            editor.prompt = match editor.prompt {
+                                Some((editor::Prompt::Language, _)) => None,
+                                _ => Some((editor::Prompt::Language, "".to_owned())),
+                            };
+                            editor.text.needs_update = true;
+                        }
+                    }
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::T), keymod, .. } => {
+                    if keymod.contains(sdl2::keyboard::Mod::LCTRLMOD) {
+                        if !editor.search_handler.active {
+                            editor.prompt = match editor.prompt {
+                                Some((editor::Prompt::Theme(_), _)) => None,
+                                _ => Some((editor::Prompt::Theme(theme::get_theme_names()), "".to_owned())),
+                            };
+                            editor.text.needs_update = true;
+                        }
+                    }
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::E), keymod, .. } => {
+                    if keymod.contains(sdl2::keyboard::Mod::LCTRLMOD) {
+                        if !editor.search_handler.active {
+                            // Opens on the newest message, Up goes back to older ones
+                            editor.prompt = match editor.prompt {
+                                Some((editor::Prompt::Messages(_), _)) => None,
+                                _ => Some((editor::Prompt::Messages(editor.messages.len().saturating_sub(1)), "".to_owned())),
+                            };
+                            editor.text.needs_update = true;
+                        }
+                    }
+                },
+
                 Event::KeyDown { keycode: Some(Keycode::F), keymod, .. } => {
                     if keymod.contains(sdl2::keyboard::Mod::LCTRLMOD) {
+                        editor.prompt = None;
                         editor.search_handler.active = !editor.search_handler.active;
                         editor.search_handler.search_string.clear();
 
@@ -215,6 +507,7 @@ fn main() {
                             let input: Vec<String> = video_subsystem.clipboard().clipboard_text().unwrap().split("\n").map(|x| x.to_owned()).collect();
                             let len = input.len();
 
+                            let y1 = editor.cursor.get_absolute_y();
                             for (i, line) in input.iter().enumerate() {
                                 editor.text.raw[editor.cursor.get_absolute_y()].insert_str(editor.cursor.x as usize, &line);
 
@@ -224,6 +517,8 @@ fn main() {
                                     editor.cursor.down(&editor.text.raw, &editor.canvas, &config);
                                 }
                             }
+                            let y2 = editor.cursor.get_absolute_y();
+                            editor.text.mark_dirty(y1, y2 + 1);
                             utils::update_timer(&mut editor);
                             editor.text.needs_update = true;
                         }
@@ -234,30 +529,64 @@ fn main() {
                     if editor.search_handler.active {
                         match editor.search_handler.next_string_pos() {
                             Some((x, y)) => {
-                                while editor.cursor.get_absolute_y() > y as usize {
-                                    editor.cursor.up(&editor.text.raw, &editor.canvas, &config);
+                                editor.cursor.go_to(x, y as usize, &editor.text.raw, &editor.canvas, &config);
+                            },
+                            None => (),
+                        }
+                    }
+                    else if editor.prompt.is_some() {
+                        let (prompt, query) = editor.prompt.clone().unwrap();
+                        let query = query.trim();
+
+                        match prompt {
+                            editor::Prompt::Language => {
+                                // An empty query goes back to detection, "plain" forces plain text
+                                if query == "" {
+                                    editor.language_locked = false;
+                                    utils::load_language(&mut editor, &config);
+                                    editor.prompt = None;
                                 }
-
-                                while editor.cursor.get_absolute_y() < y as usize {
-                                    editor.cursor.down(&editor.text.raw, &editor.canvas, &config);
+                                else if query.to_lowercase() == "plain" {
+                                    utils::set_language(&mut editor, None, &config);
+                                    editor.prompt = None;
                                 }
-
-                                while editor.cursor.x > x {
-                                    editor.cursor.left(&editor.text.raw);
+                                else {
+                                    let mut errors = Vec::new();
+                                    match detect::find_language(query, &mut errors) {
+                                        Some(language) => {
+                                            utils::set_language(&mut editor, Some(language), &config);
+                                            editor.prompt = None;
+                                        },
+                                        None => (),
+                                    }
+                                    utils::report_errors(&mut editor, &errors);
                                 }
-
-                                while editor.cursor.x < x {
-                                    editor.cursor.right(&editor.text.raw);
+                            },
+                            editor::Prompt::Theme(_) => {
+                                match theme::load_theme(query) {
+                                    Ok(theme) => {
+                                        config.apply_theme(&theme);
+                                        config.theme = query.to_owned();
+                                        utils::watch_config_files(&mut watcher, &config_layers, &config, &editor.text.file_path);
+                                        editor.prompt = None;
+                                    },
+                                    // Closed so the error shows in the status bar
+                                    Err(e) => {
+                                        utils::report_errors(&mut editor, &vec![e]);
+                                        editor.prompt = None;
+                                    },
                                 }
                             },
-                            None => (),
+                            editor::Prompt::Messages(_) => editor.prompt = None,
                         }
                     }
                     else {
                         if editor.completion_engine.list_mode {
                             let complete = &editor.completion_engine.completion_list[editor.completion_engine.selected_word][editor.completion_engine.cur_word.len()..];
 
-                            editor.text.raw[editor.cursor.get_absolute_y()].insert_str(editor.cursor.x as usize, &complete);
+                            let y = editor.cursor.get_absolute_y();
+                            editor.text.raw[y].insert_str(editor.cursor.x as usize, &complete);
+                            editor.text.mark_dirty(y, y + 1);
                             editor.cursor.x += complete.len() as u32;
 
                             editor.completion_engine.list_mode = false;
@@ -268,24 +597,17 @@ fn main() {
                             let halves: Vec<String> = editor.text.raw[editor.cursor.get_absolute_y()].split("\n").map(|x| x.to_owned()).collect();
 
                             editor.text.raw[editor.cursor.get_absolute_y()] = halves[0].clone();
+                            editor.text.raw.insert((editor.cursor.get_absolute_y()+1) as usize, halves[1].trim_start().to_owned());
 
-                            let mut space_amount = halves[0].len() - halves[0].trim_start().len();
-                            let mut space_string = "".to_owned();
-
-                            if editor.text.raw[editor.cursor.get_absolute_y()].trim().ends_with("{") ||
-                                editor.text.raw[editor.cursor.get_absolute_y()].trim().ends_with(":") ||
-                                editor.text.raw[editor.cursor.get_absolute_y()].trim().ends_with("(") {
-                                space_amount += 4;
-                            }
-
-                            for _ in 0..space_amount {
-                                space_string.push(' ');
+                            let space_amount = match &editor.indent_handler {
+                                Some(indent_handler) => indent_handler.get_line_indent(&editor.text.raw, editor.cursor.get_absolute_y()+1, &editor.settings),
+                                None => indent::get_default_next_indent(&halves[0], &editor.settings),
                             };
+                            let indent_len = indent::IndentHandler::set_line_indent(&mut editor.text.raw, editor.cursor.get_absolute_y()+1, space_amount, &editor.settings);
+                            let y = editor.cursor.get_absolute_y();
+                            editor.text.mark_dirty(y, y + 2);
 
-                            space_string.push_str(&halves[1]);
-                            editor.text.raw.insert((editor.cursor.get_absolute_y()+1) as usize, space_string);
-
-                            editor.cursor.x = space_amount as u32;
+                            editor.cursor.x = indent_len as u32;
                             editor.cursor.wanted_x = 0;
                             editor.cursor.y += 1;
                         }
@@ -301,6 +623,9 @@ fn main() {
                         editor.search_handler.search_string.pop();
                         editor.search_handler.find_search_string(&editor.text.raw);
                     }
+                    else if editor.prompt.is_some() {
+                        editor.prompt.as_mut().unwrap().1.pop();
+                    }
                     else {
                         // FIXME: This crashes sometimes
                         if editor.selected.y1 != editor.selected.y2 || editor.selected.x1 != editor.selected.x2 {
@@ -316,6 +641,7 @@ fn main() {
                                 }
                                 editor.text.raw.insert(editor.selected.y1, new_line);
                             }
+                            editor.text.mark_dirty(editor.selected.y1, editor.selected.y1 + 1);
 
                             editor.cursor.y = editor.selected.y1 as u32 - editor.cursor.screen_y;
                             editor.cursor.x = editor.selected.x1 as u32;
@@ -324,10 +650,25 @@ fn main() {
                             utils::update_timer(&mut editor);
                         }
                         else {
-                            if editor.cursor.x > 0 {
+                            let empty_pair = match &editor.pair_handler {
+                                Some(pair_handler) => pair_handler.is_empty_pair(&editor.text.raw[editor.cursor.get_absolute_y()], editor.cursor.x as usize),
+                                None => false,
+                            };
+
+                            if empty_pair {
+                                editor.cursor.right(&editor.text.raw);
+                                for _ in 0..2 {
+                                    editor.cursor.left(&editor.text.raw);
+                                    editor.text.raw[editor.cursor.get_absolute_y()].remove(editor.cursor.x as usize);
+                                }
+                                let y = editor.cursor.get_absolute_y();
+                                editor.text.mark_dirty(y, y + 1);
+                            }
+                            else if editor.cursor.x > 0 {
+                                let indent = editor.settings.get_indent_string();
                                 let amount =
-                                    if editor.text.raw[editor.cursor.get_absolute_y()][..editor.cursor.x as usize].ends_with("    ") {
-                                        4
+                                    if indent.len() > 1 && editor.text.raw[editor.cursor.get_absolute_y()][..editor.cursor.x as usize].ends_with(&indent) {
+                                        indent.len()
                                     }
                                     else {
                                         1
@@ -337,6 +678,8 @@ fn main() {
                                     editor.cursor.left(&editor.text.raw);
                                     editor.text.raw[editor.cursor.get_absolute_y()].remove(editor.cursor.x as usize);
                                 }
+                                let y = editor.cursor.get_absolute_y();
+                                editor.text.mark_dirty(y, y + 1);
                                 if editor.completion_engine.list_mode {
                                     editor.completion_engine.complete(&editor.text.raw, &editor.cursor);
                                 }
@@ -350,6 +693,8 @@ fn main() {
                                 editor.text.raw[editor.cursor.get_absolute_y()-1].push_str(&line);
 
                                 editor.text.raw.remove(editor.cursor.get_absolute_y());
+                                let y = editor.cursor.get_absolute_y() - 1;
+                                editor.text.mark_dirty(y, y + 1);
 
                                 if editor.cursor.y == 0 {
                                     editor.cursor.screen_y -= 1;
@@ -368,9 +713,11 @@ fn main() {
                 },
 
                 Event::KeyDown { keycode: Some(Keycode::Tab), .. } => {
-                    if !editor.search_handler.active {
-                        let input = "    ";
-                        editor.text.raw[editor.cursor.get_absolute_y()].insert_str(editor.cursor.x as usize, input);
+                    if !editor.search_handler.active && editor.prompt.is_none() {
+                        let input = editor.settings.get_indent_string();
+                        let y = editor.cursor.get_absolute_y();
+                        editor.text.raw[y].insert_str(editor.cursor.x as usize, &input);
+                        editor.text.mark_dirty(y, y + 1);
                         editor.cursor.x += input.len() as u32;
 
                         editor.completion_engine.list_mode = false;
@@ -384,9 +731,64 @@ fn main() {
                         editor.search_handler.search_string.push_str(&input);
                         editor.search_handler.find_search_string(&editor.text.raw);
                     }
+                    else if editor.prompt.is_some() {
+                        editor.prompt.as_mut().unwrap().1.push_str(&input);
+                    }
                     else {
-                        editor.text.raw[editor.cursor.get_absolute_y()].insert_str(editor.cursor.x as usize, &input);
-                        editor.cursor.x += input.len() as u32;
+                        let has_selection = editor.selected.x1 != editor.selected.x2 || editor.selected.y1 != editor.selected.y2;
+                        let action = match &editor.pair_handler {
+                            Some(pair_handler) => pair_handler.get_action(&editor.text.raw[editor.cursor.get_absolute_y()], editor.cursor.x as usize, &input, has_selection),
+                            None => pairs::PairAction::Insert,
+                        };
+
+                        match action {
+                            pairs::PairAction::Wrap(closer) => {
+                                editor.text.raw[editor.selected.y2].insert(editor.selected.x2, closer);
+                                editor.text.raw[editor.selected.y1].insert_str(editor.selected.x1, &input);
+                                editor.text.mark_dirty(editor.selected.y1, editor.selected.y2 + 1);
+
+                                editor.selected.x1 += input.len();
+                                if editor.selected.y1 == editor.selected.y2 {
+                                    editor.selected.x2 += input.len();
+                                }
+
+                                editor.cursor.y = editor.selected.y2 as u32 - editor.cursor.screen_y;
+                                editor.cursor.x = editor.selected.x2 as u32;
+                                editor.cursor.wanted_x = editor.cursor.x;
+
+                                utils::update_timer(&mut editor);
+                                editor.text.needs_update = true;
+                                continue;
+                            },
+                            pairs::PairAction::Skip => {
+                                editor.cursor.right(&editor.text.raw);
+                            },
+                            pairs::PairAction::Close(closer) => {
+                                let y = editor.cursor.get_absolute_y();
+                                editor.text.raw[y].insert(editor.cursor.x as usize, closer);
+                                editor.text.raw[y].insert_str(editor.cursor.x as usize, &input);
+                                editor.text.mark_dirty(y, y + 1);
+                                editor.cursor.x += input.len() as u32;
+                            },
+                            pairs::PairAction::Insert => {
+                                let y = editor.cursor.get_absolute_y();
+                                editor.text.raw[y].insert_str(editor.cursor.x as usize, &input);
+                                editor.text.mark_dirty(y, y + 1);
+                                editor.cursor.x += input.len() as u32;
+                            },
+                        }
+
+                        match &editor.indent_handler {
+                            Some(indent_handler) => {
+                                let y = editor.cursor.get_absolute_y();
+                                if indent_handler.is_electric(&input) && indent_handler.decrease.is_match(&editor.text.raw[y]) {
+                                    let diff = indent_handler.reindent_line(&mut editor.text.raw, y, &editor.settings);
+                                    editor.cursor.x = (editor.cursor.x as isize + diff) as u32;
+                                    editor.cursor.wanted_x = editor.cursor.x;
+                                }
+                            },
+                            None => (),
+                        }
 
                         if editor.completion_engine.list_mode {
                             editor.completion_engine.complete(&editor.text.raw, &editor.cursor);
@@ -402,15 +804,49 @@ fn main() {
                     editor.text.needs_update = true;
                 },
 
-                Event::MouseWheel { y: dir, .. } => {
-                    editor.cursor.scroll_screen(&editor.canvas, &editor.text.raw, dir, &config);
+                Event::MouseWheel { x: dir_x, y: dir_y, .. } => {
+                    let keymod = sdl_context.keyboard().mod_state();
+                    if keymod.intersects(sdl2::keyboard::Mod::LSHIFTMOD | sdl2::keyboard::Mod::RSHIFTMOD) {
+                        // Shift turns the wheel sideways, scrolling up moves left
+                        editor.cursor.scroll_horizontal(&editor.text, &editor.canvas, -dir_y, &config);
+                    }
+                    else {
+                        editor.cursor.scroll_screen(&editor.canvas, &editor.text.raw, dir_y, &config);
+                        editor.cursor.scroll_horizontal(&editor.text, &editor.canvas, dir_x, &config);
+                    }
                     editor.text.needs_update = true;
                 },
 
                 Event::MouseButtonDown { mouse_btn: button, x, y, .. } => {
                     match button {
                         sdl2::mouse::MouseButton::Left => {
-                            editor.cursor.move_to(x, y, &texture_creator, &mut editor.text, &config);
+                            let scrollbar = scrollbar::Scrollbar::new(&editor.canvas, editor.text.raw.len(), &config);
+                            if scrollbar.track.contains_point((x, y)) {
+                                // Clicking the track centres the thumb there and drags it from its middle
+                                let thumb = scrollbar.get_thumb(editor.cursor.screen_y as usize);
+                                let grab = if thumb.contains_point((x, y)) { y - thumb.y() } else { thumb.height() as i32/2 };
+                                editor.scrollbar_grab = Some(grab);
+
+                                editor.cursor.scroll_to(&editor.canvas, &editor.text.raw, scrollbar.get_screen_y(y - grab), &config);
+                                editor.text.needs_update = true;
+                                continue;
+                            }
+
+                            let view = minimap::View::new(&editor.canvas, editor.text.raw.len(), &config);
+                            if config.minimap && view.rect.contains_point((x, y)) {
+                                // Clicking outside the viewport centres it there and drags it from its middle
+                                let viewport = view.get_viewport(editor.cursor.screen_y as usize);
+                                let grab = if viewport.contains_point((x, y)) { y - viewport.y() } else { viewport.height() as i32/2 };
+                                editor.minimap.grab = Some(grab);
+
+                                editor.cursor.scroll_to(&editor.canvas, &editor.text.raw, view.get_screen_y(y - grab), &config);
+                                editor.text.needs_update = true;
+                                continue;
+                            }
+                            follow_cursor = true;
+
+                            let wrap_width = editor.get_wrap_width(&config);
+                            editor.cursor.move_to(x, y, &mut editor.text, &config, wrap_width);
 
                             editor.selected.old_x = editor.cursor.x as usize;
                             editor.selected.old_y = editor.cursor.get_absolute_y();
@@ -428,7 +864,15 @@ fn main() {
                 Event::MouseButtonUp { mouse_btn: button, x, y, .. } => {
                     match button {
                         sdl2::mouse::MouseButton::Left => {
-                            editor.cursor.move_to(x, y, &texture_creator, &mut editor.text, &config);
+                            if editor.scrollbar_grab.is_some() || editor.minimap.grab.is_some() {
+                                editor.scrollbar_grab = None;
+                                editor.minimap.grab = None;
+                                continue;
+                            }
+                            follow_cursor = true;
+
+                            let wrap_width = editor.get_wrap_width(&config);
+                            editor.cursor.move_to(x, y, &mut editor.text, &config, wrap_width);
 
                             if editor.selected.old_y < editor.cursor.get_absolute_y() || (editor.selected.old_y == editor.cursor.get_absolute_y() && editor.selected.old_x < editor.cursor.x as usize) {
                                 editor.selected.y1 = editor.selected.old_y as usize;
@@ -452,46 +896,50 @@ fn main() {
                 },
 
                 Event::MouseMotion { mousestate, x, y, .. } => {
+                    // The button may have been let go outside the window
+                    if !mousestate.left() {
+                        editor.scrollbar_grab = None;
+                        editor.minimap.grab = None;
+                    }
+                    match editor.scrollbar_grab {
+                        Some(grab) => {
+                            let scrollbar = scrollbar::Scrollbar::new(&editor.canvas, editor.text.raw.len(), &config);
+                            editor.cursor.scroll_to(&editor.canvas, &editor.text.raw, scrollbar.get_screen_y(y - grab), &config);
+                            editor.text.needs_update = true;
+                            continue;
+                        },
+                        None => (),
+                    }
+                    match editor.minimap.grab {
+                        Some(grab) => {
+                            let view = minimap::View::new(&editor.canvas, editor.text.raw.len(), &config);
+                            editor.cursor.scroll_to(&editor.canvas, &editor.text.raw, view.get_screen_y(y - grab), &config);
+                            editor.text.needs_update = true;
+                            continue;
+                        },
+                        None => (),
+                    }
+
                     if mousestate.left() {
                         {
-                            let (_, w_height) = editor.canvas.window().size();
+                            let (w_width, w_height) = editor.canvas.window().size();
                             if y > (w_height - 3*config.font_size as u32) as i32 {
                                 editor.cursor.scroll_screen(&editor.canvas, &editor.text.raw, -1, &config);
                             }
                             if y < 3*config.font_size as i32 {
                                 editor.cursor.scroll_screen(&editor.canvas, &editor.text.raw, 1, &config);
                             }
+                            if x > (w_width - editor.cursor.side_w - config.font_size as u32) as i32 {
+                                editor.cursor.scroll_horizontal(&editor.text, &editor.canvas, 1, &config);
+                            }
+                            if x < (editor.cursor.number_w + config.font_size as u32) as i32 {
+                                editor.cursor.scroll_horizontal(&editor.text, &editor.canvas, -1, &config);
+                            }
                         }
 
                         {
-                            let new_y =
-                                if (y/config.font_size as i32) as u32 + editor.cursor.screen_y < editor.text.raw.len() as u32 {
-                                    (y/config.font_size as i32) as usize + editor.cursor.screen_y as usize
-                                }
-                                else {
-                                    editor.text.raw.len() - 1
-                                };
-                            let new_x = {
-                                let mut width = 0;
-                                let mut len = 0;
-                                let line = editor.text.raw[new_y].clone();
-                                let mut c_iter = line.graphemes(true);
-                                let mut c = c_iter.next();
-                                while c != None {
-                                    if ((x-editor.cursor.number_w as i32)-width as i32) < config.font_size as i32/2 {
-                                        break;
-                                    }
-
-                                    let cur = c.unwrap();
-                                    let texture = editor.text.get_normal_char(&cur, &texture_creator, &::WHITE);
-                                    let texture_info = texture.query();
-
-                                    width += texture_info.width;
-                                    len += cur.len() as u32;
-                                    c = c_iter.next();
-                                }
-                                len
-                            };
+                            let wrap_width = editor.get_wrap_width(&config);
+                            let (new_y, new_x) = editor.cursor.get_hit(x, y, &editor.text, &config, wrap_width);
 
                             if editor.selected.old_y < new_y || (editor.selected.old_y == new_y && editor.selected.old_x < new_x as usize) {
                                 editor.selected.y1 = editor.selected.old_y as usize;
@@ -525,6 +973,68 @@ fn main() {
             }
         }
 
+        match &mut bench {
+            Some(bench) => {
+                if editor.cursor.screen_y as usize + 1 >= editor.text.raw.len() {
+                    bench.report(&editor.text.atlas);
+                    break 'running;
+                }
+
+                for _ in 0..bench::SCROLL_LINES {
+                    editor.cursor.scroll_screen(&editor.canvas, &editor.text.raw, -1, &config);
+                }
+                editor.text.needs_update = true;
+                bench.start_frame();
+            },
+            None => (),
+        }
+
+        // A file opened from another directory may come with other project configs and
+        // .editorconfig files
+        let mut project_changed = false;
+        let mut file_changed = false;
+        if editor.text.file_path != layers_file_path {
+            file_changed = true;
+            layers_file_path = editor.text.file_path.clone();
+            project_changed = paths::get_config_layers(explicit_config.as_ref().map(|path| path.as_str()), &layers_file_path) != config_layers;
+        }
+
+        if project_changed || file_changed || last_watch.elapsed() >= std::time::Duration::from_millis(500) {
+            last_watch = std::time::Instant::now();
+
+            let changed = watcher.changed();
+            // .editorconfig files only change the language settings
+            let is_language_path = |path: &String| utils::is_language_path(path) || editorconfig::is_editorconfig_path(path);
+            let mut reload_language = changed.iter().any(|path| is_language_path(path));
+            if project_changed || changed.iter().any(|path| !is_language_path(path)) {
+                config_layers = paths::get_config_layers(explicit_config.as_ref().map(|path| path.as_str()), &editor.text.file_path);
+                let result = config::Config::parse_config(&config_layers).and_then(|mut new_config| {
+                    editor.apply_overrides(&mut new_config);
+                    editor.apply_config(&ttf_context, &new_config, &config)?;
+                    Ok(new_config)
+                });
+
+                match result {
+                    Ok(new_config) => {
+                        config = new_config;
+                        utils::report_errors(&mut editor, &config.warnings);
+
+                        // The language settings may have changed with it
+                        reload_language = true;
+                    },
+                    Err(e) => utils::report_errors(&mut editor, &vec![e]),
+                }
+            }
+            if reload_language {
+                utils::reload_language(&mut editor, &config);
+            }
+
+            if project_changed || file_changed || changed.len() > 0 {
+                utils::watch_config_files(&mut watcher, &config_layers, &config, &editor.text.file_path);
+                editor.text.needs_update = true;
+            }
+        }
+
         if !editor.text.needs_update {
             if editor.char_timer > 60 {
                 utils::update_timer(&mut editor);
@@ -544,15 +1054,8 @@ fn main() {
         let (w_width, w_height) = editor.canvas.window().size();
 
         //Draw Lines
+        let layout;
         {
-            let screen_limit =
-                if editor.text.raw.len() < (editor.cursor.screen_y + editor.canvas.window().size().1/editor.text.font_size as u32) as usize {
-                    editor.text.raw.len()
-                }
-                else {
-                    (editor.cursor.screen_y + editor.canvas.window().size().1/editor.text.font_size as u32) as usize
-                };
-
             let digits = utils::number_of_digits(editor.text.raw.len());
             {
                 let max_number = format!["{:1$} ", editor.text.raw.len(), digits];
@@ -565,81 +1068,137 @@ fn main() {
                 editor.canvas.set_draw_color(config.bar_color);
                 editor.canvas.fill_rect(rect![0, 0, x, w_height]).unwrap();
             }
+            editor.cursor.side_w = scrollbar::SCROLLBAR_WIDTH + if config.minimap { minimap::MINIMAP_WIDTH } else { 0 };
+
+            // Wrapped lines never scroll sideways, but can take more rows than there are lines
+            let wrap_width = editor.get_wrap_width(&config);
+            if wrap_width.is_some() {
+                editor.cursor.screen_x = 0;
+                if follow_cursor {
+                    editor.cursor.fit_rows(&editor.text, &editor.canvas, &config, wrap_width);
+                }
+            }
+            else if follow_cursor {
+                editor.cursor.follow_x(&editor.text, &editor.canvas, &config);
+            }
+
+            layout = layout::Layout::new(&editor.text, editor.cursor.screen_y as usize, (w_height/editor.text.font_size as u32) as usize, wrap_width);
+            let screen_limit = layout.get_line_limit();
+
+            // Everything between the line numbers and the scrollbar or minimap scrolls sideways and
+            // is clipped to that area
+            let text_area = rect![editor.cursor.number_w, 0, w_width.saturating_sub(editor.cursor.number_w + editor.cursor.side_w), w_height];
+            editor.canvas.set_clip_rect(text_area);
+
+            //Draw rulers
+            {
+                let text_x = editor.cursor.number_w as i32 - editor.cursor.screen_x as i32;
+                let (space_w, _) = editor.text.font.size_of(" ").unwrap();
+                let (r, g, b) = config.line_number_color.rgb();
+
+                editor.canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+                editor.canvas.set_draw_color(sdl2::pixels::Color::RGBA(r, g, b, 60));
+                for column in &editor.settings.rulers {
+                    let x = text_x + (space_w * *column as u32) as i32;
+                    editor.canvas.fill_rect(rect![x, 0, 1, w_height]).unwrap();
+                }
+                editor.canvas.set_blend_mode(sdl2::render::BlendMode::None);
+            }
+
+            utils::update_syntax(&mut editor);
+
+            let bracket_depths = editor.bracket_handler.get_depths(&editor.text.raw, &editor.syntax_handler, editor.cursor.screen_y as usize, screen_limit);
+
+            editor.text.atlas.next_frame();
+            let mut batch = Vec::new();
+            let mut numbers = Vec::new();
+            let mut r = 0;
+            while r < layout.rows.len() {
+                let i = layout.rows[r].line;
 
-            for i in (editor.cursor.screen_y as usize)..screen_limit {
-                //Draw line number
+                //Draw line number, only on the first row of a wrapped line
                 let mut x = 0;
-                let y = editor.text.font_size*((i-editor.cursor.screen_y as usize) as u16);
+                let mut y = editor.text.font_size as u32*r as u32;
 
                 let number = format!["{:1$} ", i+1, digits];
                 let mut n_iter = number.graphemes(true);
                 let mut n = n_iter.next();
                 while n != None {
-                    let texture = editor.text.get_bold_char(n.unwrap(), &texture_creator, &config.line_number_color);
-                    let texture_info = texture.query();
-
-                    editor.canvas.copy(texture, None, Some(rect![x, y, texture_info.width, texture_info.height])).unwrap();
-                    x += texture_info.width;
+                    x += editor.text.queue_char(&mut numbers, n.unwrap(), sdl2::ttf::FontStyle::BOLD, config.line_number_color, x as i32, y as i32, &texture_creator);
 
                     n = n_iter.next()
                 }
+                x = editor.cursor.number_w;
 
                 //Draw line text
-                let line = editor.text.raw[i].clone();
-
-                let mut colors = syntax::SyntaxHandler::get_line_color(&line, &editor, &config).into_iter();
-
-                let mut c_iter = line.graphemes(true);
+                // Moved out rather than cloned, the glyph cache needs the text borrowed mutably
+                let line = std::mem::replace(&mut editor.text.raw[i], String::new());
+
+                let mut colors = syntax::SyntaxHandler::get_line_color(&line, i, &editor.syntax_handler, &config);
+                brackets::colorize_line(&line, i, &bracket_depths, &mut colors, &config);
+                let mut colors = colors.into_iter();
+                let mut styles = syntax::SyntaxHandler::get_line_styles(&line, i, &editor.syntax_handler, &config).into_iter();
+
+                // Laid out from the start of each row and drawn screen_x further left, stopping at
+                // the right edge
+                let line_x = x;
+                let shift = editor.cursor.screen_x;
+                let mut c_iter = line.grapheme_indices(true);
                 let mut c = c_iter.next();
-                while c != None {
-                    let texture = editor.text.get_normal_char(c.unwrap(), &texture_creator, &colors.next().unwrap());
-                    let texture_info = texture.query();
+                while c != None && (wrap_width.is_some() || x < w_width + shift) {
+                    let (j, c_str) = c.unwrap();
+                    let color = colors.next().unwrap();
+                    let style = styles.next().unwrap();
+
+                    if r + 1 < layout.rows.len() && layout.rows[r + 1].line == i && j >= layout.rows[r + 1].start {
+                        r += 1;
+                        x = line_x;
+                        y += editor.text.font_size as u32;
+                    }
+                    // The rest of the line is below the screen
+                    else if j >= layout.rows[r].end && layout.rows[r].end < line.len() {
+                        break;
+                    }
 
-                    editor.canvas.copy(texture, None, Some(rect![x, y, texture_info.width, texture_info.height])).unwrap();
-                    x += texture_info.width;
+                    if c_str == "\t" {
+                        x = line_x + editor.text.get_tab_stop(x - line_x);
+                    }
+                    else {
+                        x += editor.text.queue_char(&mut batch, c_str, style, color, x as i32 - shift as i32, y as i32, &texture_creator);
+                    }
 
                     c = c_iter.next()
                 }
+
+                editor.text.raw[i] = line;
+
+                // Drawn per line so the batch stays small, a line's glyphs mostly share a page and color
+                editor.text.draw_batch(&mut editor.canvas, &mut batch);
+
+                while r < layout.rows.len() && layout.rows[r].line == i {
+                    r += 1;
+                }
             }
+
+            // Pages drawn from this frame are never evicted, so the numbers can wait for the end
+            editor.canvas.set_clip_rect(None);
+            editor.text.draw_batch(&mut editor.canvas, &mut numbers);
+            editor.canvas.set_clip_rect(text_area);
         }
+        let text_x = editor.cursor.number_w as i32 - editor.cursor.screen_x as i32;
+        let f_s = editor.text.font_size as u32;
 
         //Draw text selection
         {
             editor.canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
             if editor.selected.x1 != editor.selected.x2 || editor.selected.y1 != editor.selected.y2 {
-                let (half, _) = editor.text.raw[editor.selected.y1].split_at(editor.selected.x1);
-                let (x1, _) = editor.text.font.size_of(half).unwrap();
-
-                let (half, _) =
-                    if editor.selected.x2 < editor.text.raw[editor.selected.y2].len() {
-                        editor.text.raw[editor.selected.y2].split_at(editor.selected.x2)
-                    }
-                    else {
-                        (&editor.text.raw[editor.selected.y2][..], "")
-                    };
-                let (x2, _) = editor.text.font.size_of(half).unwrap();
-
                 editor.canvas.set_draw_color(config.select_color);
-                if editor.selected.y1 == editor.selected.y2 {
-                    editor.canvas.fill_rect(rect![x1+editor.cursor.number_w, (editor.selected.y1 as isize - editor.cursor.screen_y as isize)*editor.text.font_size as isize, x2-x1, editor.text.font_size]).unwrap();
-                }
-                else {
-                    for i in editor.selected.y1..=editor.selected.y2 {
-                        let mut start = editor.cursor.number_w;
-                        let mut end = editor.cursor.number_w;
-                        let (all, _) = editor.text.font.size_of(&editor.text.raw[i][..]).unwrap();
-
-                        if i == editor.selected.y1 {
-                            start += x1;
-                            end += all;
-                        }
-                        else if i == editor.selected.y2 {
-                            end += x2;
-                        }
-                        else {
-                            end += all;
-                        }
-                        editor.canvas.fill_rect(rect![start, (i as isize - editor.cursor.screen_y as isize)*editor.text.font_size as isize, end-start, editor.text.font_size]).unwrap();
+                for i in editor.selected.y1..=editor.selected.y2 {
+                    let x1 = if i == editor.selected.y1 { editor.selected.x1 } else { 0 };
+                    let x2 = if i == editor.selected.y2 { editor.selected.x2.min(editor.text.raw[i].len()) } else { editor.text.raw[i].len() };
+
+                    for (row, x, w) in layout.get_spans(&editor.text, i, x1, x2) {
+                        editor.canvas.fill_rect(rect![text_x + x as i32, row as u32*f_s, w, f_s]).unwrap();
                     }
                 }
             }
@@ -650,69 +1209,130 @@ fn main() {
         {
             editor.canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
             if editor.search_handler.active {
-                for (x, y) in &editor.search_handler.found_places {
-                    if y.clone() >= editor.cursor.screen_y {
-                        let (half, _) = editor.text.raw[y.clone() as usize].split_at(x.clone() as usize);
-                        let (x1, _) = editor.text.font.size_of(half).unwrap();
-                        let (w, _) = editor.text.font.size_of(&editor.search_handler.search_string).unwrap();
+                editor.canvas.set_draw_color(config.search_color);
+                for &(x, y) in &editor.search_handler.found_places {
+                    if y >= editor.cursor.screen_y && (y as usize) < layout.get_line_limit() {
+                        let end = x as usize + editor.search_handler.search_string.len();
 
-                        editor.canvas.set_draw_color(config.search_color);
-                        editor.canvas.fill_rect(rect![x1+editor.cursor.number_w, (y-editor.cursor.screen_y)*editor.text.font_size as u32, w, editor.text.font_size]).unwrap();
+                        for (row, x, w) in layout.get_spans(&editor.text, y as usize, x as usize, end) {
+                            editor.canvas.fill_rect(rect![text_x + x as i32, row as u32*f_s, w, f_s]).unwrap();
+                        }
                     }
                 }
             }
             editor.canvas.set_blend_mode(sdl2::render::BlendMode::None);
         }
 
-        //Draw autocomplete options
+        //Draw matching brackets
         {
-            if editor.completion_engine.list_mode {
-                let x;
-                {
-                    let (half, _) = editor.text.raw[editor.cursor.get_absolute_y()].split_at(editor.cursor.x as usize);
-                    let (temp_x, _) = editor.text.font.size_of(half).unwrap();
+            editor.canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+            // A partner off screen isn't drawn, so the search stops at the screen's edges
+            let lines = editor.cursor.screen_y as usize..layout.get_line_limit();
+            match brackets::find_match(&editor.text.raw, &editor.syntax_handler, editor.cursor.x as usize, editor.cursor.get_absolute_y(), lines) {
+                Some((first, second)) => {
+                    editor.canvas.set_draw_color(config.match_color);
+                    for &(x, y) in [first, second].iter() {
+                        for (row, x, w) in layout.get_spans(&editor.text, y, x, x+1) {
+                            editor.canvas.fill_rect(rect![text_x + x as i32, row as u32*f_s, w, f_s]).unwrap();
+                        }
+                    }
+                },
+                None => (),
+            }
+            editor.canvas.set_blend_mode(sdl2::render::BlendMode::None);
+        }
 
-                    x = temp_x + editor.cursor.number_w;
-                }
-                let mut y = editor.cursor.y*(editor.text.font_size as u32);
+        // Row and x offset of the cursor, off screen when scrolled away with the mouse wheel
+        let cursor_position = layout.get_position(&editor.text, editor.cursor.get_absolute_y(), editor.cursor.x as usize);
 
-                editor.canvas.set_draw_color(config.bar_color);
-                editor.canvas.fill_rect(rect![x, y, 200, editor.completion_engine.completion_list.len()*(editor.text.font_size as usize)]).unwrap();
+        //Draw autocomplete options
+        {
+            match cursor_position {
+                Some((row, x)) if editor.completion_engine.list_mode => {
+                    let x = text_x + x as i32;
+                    let mut y = row as u32*f_s;
 
-                for word in &editor.completion_engine.completion_list {
-                    let mut c_x = x;
-                    let mut iter = word.graphemes(true);
-                    for c in iter {
-                        let texture = editor.text.get_normal_char(c, &texture_creator, &WHITE);
-                        let texture_info = texture.query();
+                    editor.canvas.set_draw_color(config.bar_color);
+                    editor.canvas.fill_rect(rect![x, y, 200, editor.completion_engine.completion_list.len()*(f_s as usize)]).unwrap();
 
-                        editor.canvas.copy(texture, None, Some(rect![c_x, y, texture_info.width, texture_info.height])).unwrap();
-                        c_x += texture_info.width;
+                    for word in &editor.completion_engine.completion_list {
+                        editor.text.draw_text(&mut editor.canvas, word, sdl2::ttf::FontStyle::NORMAL, config.bar_text_color, x, y as i32, &texture_creator);
+                        y += f_s;
                     }
-                    y += editor.text.font_size as u32;
-                }
+                },
+                _ => (),
             }
         }
 
         //Draw cursor
         {
-            editor.text.font.set_style(sdl2::ttf::FontStyle::NORMAL);
-            let (half, _) = editor.text.raw[editor.cursor.get_absolute_y()].split_at(editor.cursor.x as usize);
-            let (x, _) = editor.text.font.size_of(half).unwrap();
+            match cursor_position {
+                Some((row, x)) => {
+                    let mut texture = texture_creator.create_texture_from_surface(&editor.cursor.surface).unwrap();
+                    let (r, g, b) = config.get_scope_color("other").rgb();
+                    texture.set_color_mod(r, g, b);
+
+                    editor.canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+                    editor.canvas.set_draw_color(sdl2::pixels::Color::RGBA(255, 255, 255, 15));
+                    editor.canvas.fill_rect(rect!(editor.cursor.number_w, row as u32*f_s, w_width - editor.cursor.number_w, f_s)).unwrap();
+                    editor.canvas.set_blend_mode(sdl2::render::BlendMode::None);
+
+                    let row = if editor.completion_engine.list_mode { row + editor.completion_engine.selected_word } else { row };
+                    editor.canvas.copy(&texture, None, Some(rect![1+text_x+x as i32, row as u32*f_s, config.cursor_width, f_s])).unwrap();
+                },
+                None => (),
+            }
+        }
 
-            let texture = texture_creator.create_texture_from_surface(&editor.cursor.surface).unwrap();
+        editor.canvas.set_clip_rect(None);
 
-            editor.canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
-            editor.canvas.set_draw_color(sdl2::pixels::Color::RGBA(255, 255, 255, 15));
-            editor.canvas.fill_rect(rect!(editor.cursor.number_w, editor.cursor.y * editor.text.font_size as u32, w_width - editor.cursor.number_w, editor.text.font_size)).unwrap();
-            editor.canvas.set_blend_mode(sdl2::render::BlendMode::None);
+        if editor.text.errors.len() > 0 {
+            let errors = editor.text.errors.drain(..).collect();
+            utils::report_errors(&mut editor, &errors);
+        }
+
+        //Draw minimap
+        if config.minimap {
+            let view = minimap::View::new(&editor.canvas, editor.text.raw.len(), &config);
+            let (version, tab_width) = (editor.text.version, editor.text.tab_width);
+            match editor.minimap.draw(&mut editor.canvas, &view, &editor.text.raw, version, tab_width, &editor.syntax_handler, &config, &texture_creator) {
+                Ok(_) => {
+                    editor.canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+                    editor.canvas.set_draw_color(sdl2::pixels::Color::RGBA(255, 255, 255, 30));
+                    editor.canvas.fill_rect(view.get_viewport(editor.cursor.screen_y as usize)).unwrap();
+                    editor.canvas.set_blend_mode(sdl2::render::BlendMode::None);
+                },
+                Err(e) => utils::report_errors(&mut editor, &vec![e]),
+            }
+        }
+
+        //Draw scrollbar
+        {
+            let scrollbar = scrollbar::Scrollbar::new(&editor.canvas, editor.text.raw.len(), &config);
+            editor.canvas.set_draw_color(config.bar_color);
+            editor.canvas.fill_rect(scrollbar.track).unwrap();
+
+            // Markers are drawn opaque, the colors are meant for blending over text
+            let opaque = |color: sdl2::pixels::Color| sdl2::pixels::Color::RGB(color.r, color.g, color.b);
 
-            if editor.completion_engine.list_mode {
-                editor.canvas.copy(&texture, None, Some(rect![1+x+editor.cursor.number_w, (editor.cursor.y + editor.completion_engine.selected_word as u32)*(editor.text.font_size as u32), config.cursor_width, editor.text.font_size])).unwrap();
+            if editor.selected.x1 != editor.selected.x2 || editor.selected.y1 != editor.selected.y2 {
+                editor.canvas.set_draw_color(opaque(config.select_color));
+                editor.canvas.fill_rect(scrollbar.get_marker(editor.selected.y1, editor.selected.y2)).unwrap();
             }
-            else {
-                editor.canvas.copy(&texture, None, Some(rect![1+x+editor.cursor.number_w, editor.cursor.y*(editor.text.font_size as u32), config.cursor_width, editor.text.font_size])).unwrap();
+            if editor.search_handler.active {
+                editor.canvas.set_draw_color(opaque(config.search_color));
+                for &(_, y) in &editor.search_handler.found_places {
+                    editor.canvas.fill_rect(scrollbar.get_marker(y as usize, y as usize)).unwrap();
+                }
             }
+            editor.canvas.set_draw_color(config.line_number_color);
+            let y = editor.cursor.get_absolute_y();
+            editor.canvas.fill_rect(scrollbar.get_marker(y, y)).unwrap();
+
+            editor.canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+            editor.canvas.set_draw_color(sdl2::pixels::Color::RGBA(255, 255, 255, 40));
+            editor.canvas.fill_rect(scrollbar.get_thumb(editor.cursor.screen_y as usize)).unwrap();
+            editor.canvas.set_blend_mode(sdl2::render::BlendMode::None);
         }
 
         //Draw statusbar
@@ -723,21 +1343,10 @@ fn main() {
             //Right aligned
             {
                 let lines_ui = format!["{}/{}: {}", editor.cursor.get_absolute_y()+1, editor.text.raw.len(), editor.cursor.x+1];
-                let mut n_iter = lines_ui.graphemes(true);
-                let mut n = n_iter.next();
-                let mut x = w_width-editor.text.font.size_of(&lines_ui).unwrap().0-10;
-                while n != None {
-                    let f_s = editor.text.font_size;
-
-                    let texture = editor.text.get_normal_char(n.unwrap(), &texture_creator, &WHITE);
-                    let texture_info = texture.query();
-
-                    editor.canvas.copy(&texture, None, Some(rect![x, w_height-f_s as u32, texture_info.width, texture_info.height])).unwrap();
-
-                    n = n_iter.next();
+                let x = w_width-editor.text.font.size_of(&lines_ui).unwrap().0-10;
+                let f_s = editor.text.font_size;
 
-                    x += texture_info.width;
-                }
+                editor.text.draw_text(&mut editor.canvas, &lines_ui, sdl2::ttf::FontStyle::NORMAL, config.bar_text_color, x as i32, (w_height-f_s as u32) as i32, &texture_creator);
             }
             //Left aligned
             {
@@ -752,30 +1361,40 @@ fn main() {
                             };
                         format!["Search: {} [{}/{}]", &editor.search_handler.search_string, index, editor.search_handler.found_places.len()]
                     }
+                    else if editor.prompt.is_some() {
+                        match editor.prompt.as_ref().unwrap() {
+                            (editor::Prompt::Language, query) => format!["Language: {}", query],
+                            (editor::Prompt::Theme(names), query) => format!["Theme ({}): {}", names.join(", "), query],
+                            (editor::Prompt::Messages(i), _) => match editor.messages.get(*i) {
+                                Some(message) => format!["Messages [{}/{}]: {}", i + 1, editor.messages.len(), utils::summarize_error(message)],
+                                None => "Messages: none".to_owned(),
+                            },
+                        }
+                    }
+                    else if editor.status_message.is_some() {
+                        editor.status_message.clone().unwrap()
+                    }
                     else {
-                        format!["{}: {}", &utils::get_lang_name(&editor.text), &editor.text.file_path]
+                        format!["{}: {}", &editor.language_name, &editor.text.file_path]
                     };
 
-                let mut n_iter = lines_ui.graphemes(true);
-                let mut n = n_iter.next();
-                let mut x = 10;
-                while n != None {
-                    let f_s = editor.text.font_size;
-
-                    let texture = editor.text.get_normal_char(n.unwrap(), &texture_creator, &WHITE);
-                    let texture_info = texture.query();
-
-                    editor.canvas.copy(texture, None, Some(rect![x, w_height-f_s as u32, texture_info.width, texture_info.height])).unwrap();
+                let f_s = editor.text.font_size;
 
-                    n = n_iter.next();
-
-                    x += texture_info.width;
-                }
+                editor.text.draw_text(&mut editor.canvas, &lines_ui, sdl2::ttf::FontStyle::NORMAL, config.bar_text_color, 10, (w_height-f_s as u32) as i32, &texture_creator);
             }
         }
 
         editor.canvas.present();
 
+        match &mut bench {
+            Some(bench) => {
+                bench.end_frame(editor.text.atlas.binds);
+                editor.text.atlas.binds = 0;
+            },
+            None => (),
+        }
+
         editor.text.needs_update = false;
+        follow_cursor = false;
     }
 }
@@ -0,0 +1,214 @@
+extern crate sdl2;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+
+use ::config;
+use ::syntax;
+use ::scrollbar;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// Width of the pane left of the scrollbar, one pixel per character
+pub const MINIMAP_WIDTH: u32 = 120;
+// Each line is a row of pixels followed by a blank one
+const LINE_HEIGHT: u32 = 2;
+const TEXT_ALPHA: u32 = 180;
+
+// Where the minimap is and which lines it shows, from the top of the window down to the
+// status bar. Buffers longer than the pane are sampled so the whole of them fits
+pub struct View {
+    pub rect: Rect,
+    rows: usize,
+    line_count: usize,
+    // Lines that fit on screen
+    visible: usize,
+}
+impl View {
+    pub fn new(canvas: &Canvas<Window>, line_count: usize, config: &config::Config) -> View {
+        let (w_width, w_height) = canvas.window().size();
+        let height = w_height.saturating_sub(config.font_size as u32).max(LINE_HEIGHT);
+        let rect = Rect::new(w_width.saturating_sub(scrollbar::SCROLLBAR_WIDTH + MINIMAP_WIDTH) as i32, 0, MINIMAP_WIDTH, height);
+
+        View { rect: rect, rows: (height/LINE_HEIGHT) as usize, line_count: line_count.max(1), visible: (height/config.font_size as u32).max(1) as usize }
+    }
+
+    // Line shown on a row of the pane
+    fn get_line(&self, row: usize) -> usize {
+        if self.line_count > self.rows { row*self.line_count/self.rows } else { row }
+    }
+
+    // Pixels from the top of the pane to where a line is shown
+    fn get_y(&self, line: usize) -> u32 {
+        let row = if self.line_count > self.rows { line*self.rows/self.line_count } else { line };
+        row as u32*LINE_HEIGHT
+    }
+
+    // Rectangle over the lines on screen, at least a row high
+    pub fn get_viewport(&self, screen_y: usize) -> Rect {
+        let y = self.get_y(screen_y);
+        let end = self.get_y(screen_y + self.visible).min(self.rect.height()).max(y + LINE_HEIGHT);
+        Rect::new(self.rect.x(), y as i32, MINIMAP_WIDTH, end - y)
+    }
+
+    // First line on screen that puts the viewport's top at `y`, the inverse of get_viewport
+    pub fn get_screen_y(&self, y: i32) -> usize {
+        self.get_line(y.max(0) as usize/LINE_HEIGHT as usize).min(self.line_count - 1)
+    }
+}
+
+// Scope names and colors the cached lines were drawn with
+fn get_palette(syntax_handler: &Option<syntax::SyntaxHandler>, config: &config::Config) -> Vec<(String, Color)> {
+    let mut palette = vec![("other".to_owned(), config.get_scope_color("other"))];
+    match syntax_handler {
+        Some(syntax_handler) => palette.extend(syntax_handler.scopes.iter().map(|scope| (scope.clone(), config.get_scope_color(scope)))),
+        None => (),
+    }
+    palette
+}
+
+// ARGB pixels of a line, one per column for each character that isn't whitespace
+fn render_line(line: &str, y: usize, tab_width: u32, syntax_handler: &Option<syntax::SyntaxHandler>, config: &config::Config) -> Vec<u8> {
+    let colors = syntax::SyntaxHandler::get_line_color(line, y, syntax_handler, config);
+    let tab_width = tab_width.max(1);
+
+    let mut pixels = Vec::new();
+    let mut x = 0;
+    for (c, color) in line.graphemes(true).zip(colors) {
+        if x >= MINIMAP_WIDTH {
+            break;
+        }
+
+        let (columns, pixel) = if c == "\t" {
+            ((x/tab_width + 1)*tab_width - x, 0)
+        }
+        else if c.trim().is_empty() {
+            (1, 0)
+        }
+        else {
+            (1, TEXT_ALPHA << 24 | (color.r as u32) << 16 | (color.g as u32) << 8 | color.b as u32)
+        };
+
+        for _ in 0..columns {
+            pixels.extend_from_slice(&pixel.to_ne_bytes());
+        }
+        x += columns;
+    }
+
+    pixels.truncate(MINIMAP_WIDTH as usize*4);
+    pixels
+}
+
+fn hash_line(line: &str, state: &syntax::LexState) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line.hash(&mut hasher);
+    state.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Draws the buffer in miniature into a texture kept between frames, only drawing and uploading
+// again the rows whose line changed
+pub struct Minimap<'r> {
+    // Where the viewport was grabbed while it is being dragged
+    pub grab: Option<i32>,
+
+    // What each row of the texture shows, as the line and a hash of its text and lexer state
+    rows: Vec<Option<(usize, u64)>>,
+    // Text::version the rows were last checked against
+    version: Option<u64>,
+    palette: Vec<(String, Color)>,
+    tab_width: u32,
+    texture: Option<Texture<'r>>,
+    pixels: Vec<u8>,
+}
+impl<'r> Minimap<'r> {
+    pub fn new() -> Minimap<'r> {
+        Minimap { grab: None, rows: Vec::new(), version: None, palette: Vec::new(), tab_width: 0, texture: None, pixels: Vec::new() }
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, view: &View, text: &Vec<String>, version: u64, tab_width: u32, syntax_handler: &Option<syntax::SyntaxHandler>,
+                config: &config::Config, texture_creator: &'r TextureCreator<WindowContext>) -> Result<(), String> {
+        let height = view.rect.height();
+        let pitch = MINIMAP_WIDTH as usize*4;
+        let resized = match &self.texture {
+            Some(texture) => texture.query().height != height,
+            None => true,
+        };
+        if resized {
+            let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::ARGB8888, MINIMAP_WIDTH, height).map_err(|e| e.to_string())?;
+            texture.set_blend_mode(BlendMode::Blend);
+            self.texture = Some(texture);
+        }
+
+        // A new size, theme, language or tab width changes every row
+        let palette = get_palette(syntax_handler, config);
+        let redraw = resized || palette != self.palette || tab_width != self.tab_width || view.rows != self.rows.len();
+        if redraw {
+            self.palette = palette;
+            self.tab_width = tab_width;
+            self.rows = vec![None; view.rows];
+            self.pixels = vec![0; pitch*height as usize];
+        }
+
+        if redraw || self.version != Some(version) {
+            self.version = Some(version);
+
+            let row_size = LINE_HEIGHT as usize*pitch;
+            let mut changed: Option<(usize, usize)> = None;
+            for row in 0..view.rows {
+                let y = view.get_line(row);
+                let key = if y < text.len() {
+                    let state = match syntax_handler {
+                        Some(syntax_handler) => syntax_handler.get_line_state(y),
+                        None => syntax::LexState::default(),
+                    };
+                    Some((y, hash_line(&text[y], &state)))
+                }
+                else {
+                    None
+                };
+                if key == self.rows[row] {
+                    continue;
+                }
+
+                let offset = row*row_size;
+                for byte in &mut self.pixels[offset..offset + row_size] {
+                    *byte = 0;
+                }
+                match key {
+                    Some(_) => {
+                        let pixels = render_line(&text[y], y, tab_width, syntax_handler, config);
+                        self.pixels[offset..offset + pixels.len()].copy_from_slice(&pixels);
+                    },
+                    None => (),
+                }
+                self.rows[row] = key;
+                changed = Some(changed.map_or((row, row), |(first, _)| (first, row)));
+            }
+
+            let texture = self.texture.as_mut().unwrap();
+            if redraw {
+                texture.update(None, &self.pixels, pitch).map_err(|e| e.to_string())?;
+            }
+            else {
+                match changed {
+                    Some((first, last)) => {
+                        let rect = Rect::new(0, (first*LINE_HEIGHT as usize) as i32, MINIMAP_WIDTH, ((last - first + 1)*LINE_HEIGHT as usize) as u32);
+                        texture.update(rect, &self.pixels[first*row_size..(last + 1)*row_size], pitch).map_err(|e| e.to_string())?;
+                    },
+                    None => (),
+                }
+            }
+        }
+
+        canvas.set_draw_color(config.bg_color);
+        canvas.fill_rect(view.rect)?;
+        canvas.copy(self.texture.as_ref().unwrap(), None, view.rect)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+extern crate serde;
+extern crate serde_json;
+
+use ::utils;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PairsJSON {
+    pairs: Vec<String>,
+}
+
+pub enum PairAction {
+    Insert,
+    Close(char),
+    Wrap(char),
+    Skip,
+}
+
+#[derive(Debug)]
+pub struct PairHandler {
+    pub pairs: Vec<(char, char)>,
+}
+impl PairHandler {
+    pub fn parse_pairs_file(path: &str) -> Result<PairHandler, Vec<String>> {
+        let file = utils::read_file(path);
+
+        let decoded: Result<PairsJSON, serde_json::Error> = serde_json::from_str(&file);
+
+        match decoded {
+            Ok(decoded) => Self::from_pairs(&decoded.pairs),
+            Err(e) => Err(vec![e.to_string()]),
+        }
+    }
+
+    pub fn from_pairs(entries: &Vec<String>) -> Result<PairHandler, Vec<String>> {
+        let mut pairs = Vec::new();
+        let mut errors = Vec::new();
+        for (i, pair) in entries.iter().enumerate() {
+            let chars: Vec<char> = pair.chars().collect();
+            if chars.len() == 2 {
+                pairs.push((chars[0], chars[1]));
+            }
+            else {
+                errors.push(format!["pairs[{}]: \"{}\" is not two characters", i, pair]);
+            }
+        }
+
+        if errors.len() > 0 {
+            return Err(errors);
+        }
+        Ok(PairHandler { pairs: pairs })
+    }
+
+    fn get_closer(&self, c: char) -> Option<char> {
+        for &(open, close) in &self.pairs {
+            if open == c {
+                return Some(close);
+            }
+        }
+        None
+    }
+
+    fn is_closer(&self, c: char) -> bool {
+        self.pairs.iter().any(|&(_, close)| close == c)
+    }
+
+    pub fn get_action(&self, line: &str, x: usize, input: &str, has_selection: bool) -> PairAction {
+        let mut chars = input.chars();
+        let c = match (chars.next(), chars.next()) {
+            (Some(c), None) => c,
+            _ => return PairAction::Insert,
+        };
+
+        let next = line[x..].chars().next();
+        let prev = line[..x].chars().rev().next();
+
+        if has_selection {
+            match self.get_closer(c) {
+                Some(close) => return PairAction::Wrap(close),
+                None => return PairAction::Insert,
+            }
+        }
+
+        if self.is_closer(c) && next == Some(c) {
+            return PairAction::Skip;
+        }
+
+        match self.get_closer(c) {
+            Some(close) => {
+                let next_ok = match next {
+                    Some(n) => n.is_whitespace() || self.is_closer(n),
+                    None => true,
+                };
+                let prev_ok = match prev {
+                    Some(p) => c != close || !(p.is_alphanumeric() || p == '_'),
+                    None => true,
+                };
+
+                if next_ok && prev_ok {
+                    PairAction::Close(close)
+                }
+                else {
+                    PairAction::Insert
+                }
+            },
+            None => PairAction::Insert,
+        }
+    }
+
+    pub fn is_empty_pair(&self, line: &str, x: usize) -> bool {
+        let next = line[x..].chars().next();
+        let prev = line[..x].chars().rev().next();
+
+        match (prev, next) {
+            (Some(p), Some(n)) => self.get_closer(p) == Some(n),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+const APP_NAME: &str = "aurum";
+pub const CONFIG_FILE: &str = "config.json";
+pub const PROJECT_CONFIG_FILE: &str = ".aurum.json";
+
+fn env_dir(var: &str) -> Option<PathBuf> {
+    match env::var_os(var) {
+        Some(ref value) if !value.is_empty() => Some(PathBuf::from(value)),
+        _ => None,
+    }
+}
+
+fn home_dir(relative: &str) -> Option<PathBuf> {
+    env_dir("HOME").map(|home| home.join(relative))
+}
+
+fn push_dir(dirs: &mut Vec<String>, dir: PathBuf) {
+    let dir = dir.display().to_string();
+    if Path::new(&dir).is_dir() && !dirs.contains(&dir) {
+        dirs.push(dir);
+    }
+}
+
+// Relative paths are relative to the working directory; "." and ".." are resolved without
+// following symlinks, so files that don't exist yet work too
+pub fn make_absolute(path: &str) -> String {
+    let path = match env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => PathBuf::from(path),
+    };
+
+    let mut absolute = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => { absolute.pop(); },
+            component => absolute.push(component.as_os_str()),
+        }
+    }
+    absolute.display().to_string()
+}
+
+// $XDG_CONFIG_HOME/aurum, ~/.config/aurum when it isn't set
+pub fn get_config_dir() -> Option<String> {
+    env_dir("XDG_CONFIG_HOME").or_else(|| home_dir(".config")).map(|dir| dir.join(APP_NAME).display().to_string())
+}
+
+// Directories that may hold langs/, themes/, fonts and the default config, most specific first:
+// the user's config dir, $XDG_DATA_HOME, each of $XDG_DATA_DIRS and finally the working
+// directory, so running from a checkout of the repository still works
+pub fn get_data_dirs() -> Vec<String> {
+    let mut dirs = Vec::new();
+
+    match get_config_dir() {
+        Some(dir) => push_dir(&mut dirs, PathBuf::from(dir)),
+        None => (),
+    }
+    match env_dir("XDG_DATA_HOME").or_else(|| home_dir(".local/share")) {
+        Some(dir) => push_dir(&mut dirs, dir.join(APP_NAME)),
+        None => (),
+    }
+
+    let data_dirs = match env::var("XDG_DATA_DIRS") {
+        Ok(ref value) if value != "" => value.clone(),
+        _ => "/usr/local/share:/usr/share".to_owned(),
+    };
+    for dir in data_dirs.split(':').filter(|d| *d != "") {
+        push_dir(&mut dirs, Path::new(dir).join(APP_NAME));
+    }
+
+    match env::current_dir() {
+        Ok(dir) => push_dir(&mut dirs, dir),
+        Err(_) => (),
+    }
+
+    dirs
+}
+
+// Every data dir's subdirectory `name` that exists, most specific first
+pub fn get_data_subdirs(name: &str) -> Vec<String> {
+    get_data_dirs().iter().map(|dir| format!["{}/{}", dir, name]).filter(|dir| Path::new(dir).is_dir()).collect()
+}
+
+pub fn find_data_file(relative: &str) -> Option<String> {
+    get_data_dirs().iter().map(|dir| format!["{}/{}", dir, relative]).find(|path| Path::new(path).exists())
+}
+
+// Relative font paths are looked up in the data dirs and their fonts/ subdirectories
+pub fn resolve_font_path(font_path: &str) -> String {
+    if Path::new(font_path).is_absolute() {
+        return font_path.to_owned();
+    }
+
+    match find_data_file(font_path).or_else(|| find_data_file(&format!["fonts/{}", font_path])) {
+        Some(path) => path,
+        None => font_path.to_owned(),
+    }
+}
+
+// Config files merged into the final config, lowest priority first: the bundled defaults, the
+// user's config (or the file given with --config), then every .aurum.json from the filesystem
+// root down to the directory of `file_path`, or the working directory while no file is open
+pub fn get_config_layers(explicit: Option<&str>, file_path: &str) -> Vec<String> {
+    let mut layers = Vec::new();
+
+    let user_config = match get_config_dir() {
+        Some(dir) => format!["{}/{}", dir, CONFIG_FILE],
+        None => "".to_owned(),
+    };
+
+    // The bundled config is the first one found outside the user's config dir
+    let bundled = get_data_dirs().into_iter().map(|dir| format!["{}/{}", dir, CONFIG_FILE]).find(|path| *path != user_config && Path::new(path).is_file());
+    match bundled {
+        Some(path) => layers.push(path),
+        None => (),
+    }
+
+    match explicit {
+        Some(path) => layers.push(path.to_owned()),
+        None => {
+            if Path::new(&user_config).is_file() {
+                layers.push(user_config);
+            }
+        },
+    }
+
+    let mut project = Vec::new();
+    match env::current_dir() {
+        Ok(cwd) => {
+            // Relative paths are relative to the working directory
+            let dir = match Path::new(file_path).parent() {
+                Some(parent) if file_path != "" => cwd.join(parent),
+                _ => cwd,
+            };
+            for ancestor in dir.ancestors() {
+                let path = ancestor.join(PROJECT_CONFIG_FILE);
+                if path.is_file() {
+                    project.push(path.display().to_string());
+                }
+            }
+        },
+        Err(_) => (),
+    }
+    for path in project.into_iter().rev() {
+        if !layers.contains(&path) {
+            layers.push(path);
+        }
+    }
+
+    layers
+}
@@ -0,0 +1,65 @@
+extern crate sdl2;
+
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use ::config;
+
+// Width of the bar along the right edge, taken from the text area
+pub const SCROLLBAR_WIDTH: u32 = 14;
+const MIN_THUMB_HEIGHT: u32 = 20;
+const MIN_MARKER_HEIGHT: u32 = 2;
+
+// Geometry of the scrollbar for the current window and buffer, the track running from the top
+// of the window down to the status bar
+pub struct Scrollbar {
+    pub track: Rect,
+    line_count: usize,
+    // Lines that fit above the status bar
+    visible: usize,
+}
+impl Scrollbar {
+    pub fn new(canvas: &Canvas<Window>, line_count: usize, config: &config::Config) -> Scrollbar {
+        let (w_width, w_height) = canvas.window().size();
+        let height = w_height.saturating_sub(config.font_size as u32).max(1);
+        let track = Rect::new(w_width.saturating_sub(SCROLLBAR_WIDTH) as i32, 0, SCROLLBAR_WIDTH, height);
+
+        Scrollbar { track: track, line_count: line_count.max(1), visible: (height/config.font_size as u32).max(1) as usize }
+    }
+
+    // The first line can go down to the last one, so the view spans that many lines plus a screen
+    fn get_thumb_height(&self) -> u32 {
+        let total = self.line_count - 1 + self.visible;
+        let height = (self.track.height() as u64*self.visible as u64/total as u64) as u32;
+        height.max(MIN_THUMB_HEIGHT).min(self.track.height())
+    }
+
+    pub fn get_thumb(&self, screen_y: usize) -> Rect {
+        let height = self.get_thumb_height();
+        let range = (self.track.height() - height) as u64;
+        let y = match self.line_count {
+            1 => 0,
+            n => (range*screen_y.min(n - 1) as u64/(n - 1) as u64) as i32,
+        };
+        Rect::new(self.track.x(), y, SCROLLBAR_WIDTH, height)
+    }
+
+    // First line on screen when the thumb's top is at `y`
+    pub fn get_screen_y(&self, y: i32) -> usize {
+        let range = (self.track.height() - self.get_thumb_height()) as i64;
+        if range == 0 {
+            return 0;
+        }
+        let y = (y as i64).max(0).min(range);
+        ((y*(self.line_count - 1) as i64 + range/2)/range) as usize
+    }
+
+    // Overview marker for lines y1 to y2 of the buffer
+    pub fn get_marker(&self, y1: usize, y2: usize) -> Rect {
+        let height = self.track.height() as u64;
+        let top = height*y1 as u64/self.line_count as u64;
+        let bottom = height*(y2 + 1) as u64/self.line_count as u64;
+        Rect::new(self.track.x(), top as i32, SCROLLBAR_WIDTH, ((bottom - top) as u32).max(MIN_MARKER_HEIGHT))
+    }
+}
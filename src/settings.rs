@@ -0,0 +1,62 @@
+extern crate serde;
+extern crate serde_json;
+
+use ::encoding;
+
+// Editing behaviour that depends on the language, set per langs/<id> identifier in the
+// "languages" block of the config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LanguageSettings {
+    pub indent_width: usize,
+    pub use_tabs: bool,
+    // Overrides the config's tab_width for the buffer, which fills it in when nothing else does
+    pub tab_width: Option<u32>,
+    pub trim_trailing_whitespace: bool,
+    pub rulers: Vec<usize>,
+    pub word_wrap: bool,
+
+    pub line_comment: Option<String>,
+    pub block_comment: Option<(String, String)>,
+
+    // Replaces the language's pairs.json, each entry being an opening and a closing character
+    pub pairs: Option<Vec<String>>,
+
+    // Used when saving; a file keeps the line endings it was opened with and is UTF-8 otherwise
+    pub end_of_line: Option<encoding::LineEnding>,
+    pub charset: Option<encoding::Charset>,
+    // Some(false) removes the final newline instead of leaving the file as it is
+    pub insert_final_newline: Option<bool>,
+}
+impl LanguageSettings {
+    // What one level of indentation inserts
+    pub fn get_indent_string(&self) -> String {
+        if self.use_tabs {
+            "\t".to_owned()
+        }
+        else {
+            " ".repeat(self.indent_width)
+        }
+    }
+}
+impl Default for LanguageSettings {
+    fn default() -> LanguageSettings {
+        LanguageSettings {
+            indent_width: 4,
+            use_tabs: false,
+            tab_width: None,
+            trim_trailing_whitespace: false,
+            rulers: Vec::new(),
+            word_wrap: false,
+
+            line_comment: None,
+            block_comment: None,
+
+            pairs: None,
+
+            end_of_line: None,
+            charset: None,
+            insert_final_newline: None,
+        }
+    }
+}
@@ -3,130 +3,819 @@ extern crate serde_json;
 extern crate regex;
 
 use ::utils;
-use ::editor;
 use ::config;
 
 use sdl2::pixels::Color;
+use sdl2::ttf::FontStyle;
 
 use unicode_segmentation::UnicodeSegmentation;
 
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyntaxJSON {
+    #[serde(default)]
+    pub rules: Vec<RuleJSON>,
+    #[serde(default)]
+    pub regions: Vec<RegionJSON>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s_constant: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s_keyword: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s_secondary_word: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s_preproc: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s_data_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s_comment: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
-struct SyntaxJSON {
-    s_constant: String,
-    s_keyword: String,
-    s_secondary_word: String,
-    s_preproc: String,
-    s_data_type: String,
-    s_comment: String,
+pub struct RuleJSON {
+    pub scope: String,
+    #[serde(rename = "match")]
+    pub pattern: String,
+    #[serde(default)]
+    pub group: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegionJSON {
+    pub begin: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
+    pub scope: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub escape: Option<char>,
 }
 
 #[derive(Debug)]
-pub struct SyntaxColor {
-    pub constant: Color,
-    pub keyword: Color,
-    pub secondary_word: Color,
-    pub preproc: Color,
-    pub data_type: Color,
-    pub comment: Color,
-    pub other: Color,
+pub struct SyntaxRule {
+    pub scope: usize,
+    pub regex: regex::Regex,
+    pub group: usize,
+}
+
+#[derive(Debug)]
+pub struct SyntaxRegion {
+    pub begin: regex::Regex,
+    pub end: Option<String>,
+    end_regex: Option<regex::Regex>,
+    pub scope: usize,
+    pub escape: Option<char>,
+}
+
+// Lexer state carried from the end of one line to the start of the next
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct LexState {
+    pub region: Option<(usize, String)>,
+}
+
+#[derive(Debug)]
+struct LineState {
+    text: String,
+    start: LexState,
+    end: LexState,
+    // (start, end, scope) byte spans, kept from when the line was last lexed
+    spans: Vec<(usize, usize, usize)>,
+}
+
+// Scopes whose text is not code, e.g. brackets in them are not matched
+const LITERAL_SCOPES: [&str; 3] = ["comment", "string", "constant"];
+
+pub fn get_scope_root(scope: &str) -> &str {
+    scope.split('.').next().unwrap()
+}
+
+fn add_scope(scopes: &mut Vec<String>, name: &str) -> usize {
+    match scopes.iter().position(|s| s == name) {
+        Some(i) => i,
+        None => {
+            scopes.push(name.to_owned());
+            scopes.len() - 1
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct SyntaxHandler {
-    pub constant: regex::Regex,
-    pub keyword: regex::Regex,
-    pub secondary_word: regex::Regex,
-    pub preproc: regex::Regex,
-    pub data_type: regex::Regex,
-    pub comment: regex::Regex,
+    pub scopes: Vec<String>,
+    pub rules: Vec<SyntaxRule>,
+    pub regions: Vec<SyntaxRegion>,
+
+    states: Vec<LineState>,
 }
 impl SyntaxHandler {
-    pub fn parse_syntax_file(path: &str) -> Option<SyntaxHandler> {
+    // Errors are "field: message", e.g. "rules[2].match: regex parse error: ..."
+    pub fn parse_syntax_file(path: &str) -> Result<SyntaxHandler, Vec<String>> {
         let file = utils::read_file(path);
 
         let decoded: Result<SyntaxJSON, serde_json::Error> = serde_json::from_str(&file);
 
         match decoded {
             Ok(decoded) => {
-                return Some(SyntaxHandler {
-                    constant: regex::Regex::new(&decoded.s_constant).unwrap(),
-                    keyword: regex::Regex::new(&decoded.s_keyword).unwrap(),
-                    secondary_word: regex::Regex::new(&decoded.s_secondary_word).unwrap(),
-                    preproc: regex::Regex::new(&decoded.s_preproc).unwrap(),
-                    data_type: regex::Regex::new(&decoded.s_data_type).unwrap(),
-                    comment: regex::Regex::new(&decoded.s_comment).unwrap(),
-                })
+                return Self::from_syntax_json(decoded);
             },
             Err(e) => {
-                println!["{}", e];
-                return None;
+                return Err(vec![e.to_string()]);
             }
         }
     }
 
-    pub fn get_line_color(line: &str, editor: &editor::Editor, config: &config::Config) -> Vec<Color> {
-        let mut ret: Vec<Color> = Vec::new();
+    pub fn from_syntax_json(decoded: SyntaxJSON) -> Result<SyntaxHandler, Vec<String>> {
+        let mut scopes = Vec::new();
+        let mut errors = Vec::new();
 
-        match &editor.syntax_handler {
-            Some(structs) => {
-                let colors = &config.syntax_color;
-
-                let mut cur_x = 0;
-
-                let mut c_iter = line.graphemes(true);
-                let mut m_iter_vec = vec![structs.constant.find_iter(line),
-                                          structs.keyword.find_iter(line),
-                                          structs.secondary_word.find_iter(line),
-                                          structs.preproc.find_iter(line),
-                                          structs.data_type.find_iter(line),
-                                          structs.comment.find_iter(line)];
-
-                let mut m_vec = vec![m_iter_vec[0].next(),
-                                     m_iter_vec[1].next(),
-                                     m_iter_vec[2].next(),
-                                     m_iter_vec[3].next(),
-                                     m_iter_vec[4].next(),
-                                     m_iter_vec[5].next()];
-
-                for c in c_iter {
-                    let mut cur_color = 10;
-                    let mut i = 0;
-                    for m in m_vec.clone() {
-                        match m {
-                            Some(m) => {
-                                if m.start() <= cur_x && cur_x < m.end() {
-                                    cur_color = i;
-                                }
-                                if cur_x == m.end()-1 {
-                                    m_vec[i] = m_iter_vec[i].next();
-                                }
-                            },
-                            None => ()
+        let mut rule_list: Vec<(String, RuleJSON)> = decoded.rules.into_iter().enumerate().map(|(i, rule)| (format!["rules[{}]", i], rule)).collect();
+
+        // Old six-field files: the last category used to win, so it goes first
+        let legacy = vec![("comment", "s_comment", decoded.s_comment),
+                          ("data_type", "s_data_type", decoded.s_data_type),
+                          ("preproc", "s_preproc", decoded.s_preproc),
+                          ("secondary_word", "s_secondary_word", decoded.s_secondary_word),
+                          ("keyword", "s_keyword", decoded.s_keyword),
+                          ("constant", "s_constant", decoded.s_constant)];
+        for (scope, field, pattern) in legacy {
+            match pattern {
+                Some(pattern) => rule_list.push((field.to_owned(), RuleJSON { scope: scope.to_owned(), pattern: pattern, group: 0 })),
+                None => (),
+            }
+        }
+
+        let mut rules = Vec::new();
+        for (field, rule) in rule_list {
+            let pattern_field = if field.starts_with("rules") { format!["{}.match", field] } else { field.clone() };
+            match regex::Regex::new(&rule.pattern) {
+                Ok(regex) => {
+                    if rule.group >= regex.captures_len() {
+                        errors.push(format!["{}.group: pattern has no group {}", field, rule.group]);
+                        continue;
+                    }
+
+                    rules.push(SyntaxRule {
+                        scope: add_scope(&mut scopes, &rule.scope),
+                        regex: regex,
+                        group: rule.group,
+                    });
+                },
+                Err(e) => errors.push(format!["{}: {}", pattern_field, e]),
+            }
+        }
+
+        let mut regions = Vec::new();
+        for (i, region) in decoded.regions.into_iter().enumerate() {
+            let begin = match regex::Regex::new(&region.begin) {
+                Ok(begin) => begin,
+                Err(e) => {
+                    errors.push(format!["regions[{}].begin: {}", i, e]);
+                    continue;
+                }
+            };
+
+            // Ends referring to the begin match (\1) can only be compiled once the begin is found
+            let end_regex = match &region.end {
+                Some(end) if end.contains("\\1") => {
+                    if begin.captures_len() < 2 {
+                        errors.push(format!["regions[{}].end: refers to \\1 but begin has no group", i]);
+                        continue;
+                    }
+                    match regex::Regex::new(&end.replace("\\1", "")) {
+                        Ok(_) => None,
+                        Err(e) => {
+                            errors.push(format!["regions[{}].end: {}", i, e]);
+                            continue;
                         }
-                        i += 1;
                     }
+                },
+                Some(end) => {
+                    match regex::Regex::new(end) {
+                        Ok(end_regex) => Some(end_regex),
+                        Err(e) => {
+                            errors.push(format!["regions[{}].end: {}", i, e]);
+                            continue;
+                        }
+                    }
+                },
+                None => None,
+            };
+
+            regions.push(SyntaxRegion {
+                begin: begin,
+                end: region.end,
+                end_regex: end_regex,
+                scope: add_scope(&mut scopes, &region.scope),
+                escape: region.escape,
+            });
+        }
+
+        if errors.len() > 0 {
+            return Err(errors);
+        }
+
+        Ok(SyntaxHandler {
+            scopes: scopes,
+            rules: rules,
+            regions: regions,
+
+            states: Vec::new(),
+        })
+    }
+
+    pub fn is_literal(&self, scope: Option<usize>) -> bool {
+        match scope {
+            Some(scope) => LITERAL_SCOPES.contains(&get_scope_root(&self.scopes[scope])),
+            None => false,
+        }
+    }
 
-                    match cur_color {
-                        0 => ret.push(colors.constant),
-                        1 => ret.push(colors.keyword),
-                        2 => ret.push(colors.secondary_word),
-                        3 => ret.push(colors.preproc),
-                        4 => ret.push(colors.data_type),
-                        5 => ret.push(colors.comment),
-                        _ => ret.push(colors.other),
+    fn find_region_end(&self, line: &str, pos: usize, index: usize, end: &str) -> Option<usize> {
+        let region = &self.regions[index];
+
+        let dynamic_regex;
+        let end_regex = match &region.end_regex {
+            Some(end_regex) => end_regex,
+            None => {
+                dynamic_regex = match regex::Regex::new(end) {
+                    Ok(regex) => regex,
+                    Err(_) => return None,
+                };
+                &dynamic_regex
+            }
+        };
+
+        let mut start = pos;
+        loop {
+            match end_regex.find_at(line, start) {
+                Some(m) => {
+                    match region.escape {
+                        Some(escape) => {
+                            let escapes = line[pos..m.start()].chars().rev().take_while(|&c| c == escape).count();
+                            if escapes % 2 == 1 {
+                                match line[m.start()..].chars().next() {
+                                    Some(c) => {
+                                        start = m.start() + c.len_utf8();
+                                        continue;
+                                    },
+                                    None => return None,
+                                }
+                            }
+                        },
+                        None => (),
                     }
+                    return Some(m.end());
+                },
+                None => return None,
+            }
+        }
+    }
+
+    // Leftmost match of a rule or region begin at or after pos, skipping empty matches
+    fn find_token(&self, line: &str, pos: usize, index: usize) -> Option<(usize, usize, Option<(usize, usize)>)> {
+        let (regex, is_region) =
+            if index < self.regions.len() {
+                (&self.regions[index].begin, true)
+            }
+            else {
+                (&self.rules[index - self.regions.len()].regex, false)
+            };
 
-                    cur_x += c.len()
+        let mut locs = regex.capture_locations();
+        let mut start = pos;
+        while start <= line.len() {
+            match regex.captures_read_at(&mut locs, line, start) {
+                Some(m) => {
+                    if m.end() > m.start() {
+                        let group = if is_region { locs.get(1) } else { None };
+                        return Some((m.start(), m.end(), group));
+                    }
+
+                    match line[m.start()..].chars().next() {
+                        Some(c) => start = m.start() + c.len_utf8(),
+                        None => return None,
+                    }
+                },
+                None => return None,
+            }
+        }
+        None
+    }
+
+    // Splits a line into (start, end, scope) tokens. At each position the leftmost match wins,
+    // then the longest, remaining ties going to regions and then to whichever rule is listed
+    // first, and a token consumes its text so nothing inside a string or comment gets matched
+    // again
+    pub fn tokenize(&self, line: &str, state: &LexState) -> (Vec<(usize, usize, usize)>, LexState) {
+        let mut tokens = Vec::new();
+        let mut state = state.clone();
+        let mut pos = 0;
+
+        match state.region.clone() {
+            Some((index, end)) => {
+                match self.find_region_end(line, 0, index, &end) {
+                    Some(end_pos) => {
+                        tokens.push((0, end_pos, self.regions[index].scope));
+                        state.region = None;
+                        pos = end_pos;
+                    },
+                    None => {
+                        tokens.push((0, line.len(), self.regions[index].scope));
+                        return (tokens, state);
+                    }
+                }
+            },
+            None => (),
+        }
+
+        let candidates: Vec<usize> = (0..self.regions.len() + self.rules.len()).filter(|&i| i < self.regions.len() || self.rules[i - self.regions.len()].group == 0).collect();
+        let mut found: Vec<Option<(usize, usize, Option<(usize, usize)>)>> = vec![None; candidates.len()];
+        let mut searched: Vec<bool> = vec![false; candidates.len()];
+
+        loop {
+            let mut best: Option<usize> = None;
+            for (slot, &index) in candidates.iter().enumerate() {
+                // A cached match is still the leftmost one as long as it starts after pos
+                let stale = match found[slot] {
+                    Some((start, _, _)) => start < pos,
+                    None => !searched[slot],
+                };
+                if stale {
+                    found[slot] = self.find_token(line, pos, index);
+                    searched[slot] = true;
+                }
+
+                match (found[slot], best) {
+                    (Some((start, end, _)), Some(b)) => {
+                        let (best_start, best_end, _) = found[b].unwrap();
+                        if start < best_start || (start == best_start && end > best_end) {
+                            best = Some(slot);
+                        }
+                    },
+                    (Some(_), None) => best = Some(slot),
+                    _ => (),
+                }
+            }
+
+            let slot = match best {
+                Some(slot) => slot,
+                None => break,
+            };
+            let index = candidates[slot];
+            let (start, end, group) = found[slot].unwrap();
+
+            if index >= self.regions.len() {
+                tokens.push((start, end, self.rules[index - self.regions.len()].scope));
+                pos = end;
+                continue;
+            }
+
+            let region = &self.regions[index];
+            match &region.end {
+                Some(region_end) => {
+                    let region_end = match group {
+                        Some((s, e)) => region_end.replace("\\1", &regex::escape(&line[s..e])),
+                        None => region_end.replace("\\1", ""),
+                    };
+
+                    match self.find_region_end(line, end, index, &region_end) {
+                        Some(end_pos) => {
+                            tokens.push((start, end_pos, region.scope));
+                            pos = end_pos;
+                        },
+                        None => {
+                            tokens.push((start, line.len(), region.scope));
+                            state.region = Some((index, region_end));
+                            break;
+                        }
+                    }
+                },
+                None => {
+                    tokens.push((start, end, region.scope));
+                    pos = end;
+                }
+            }
+        }
+
+        (tokens, state)
+    }
+
+    // Non-overlapping (start, end, scope) byte spans of a line, in order
+    fn get_line_spans(&self, line: &str, state: &LexState) -> Vec<(usize, usize, usize)> {
+        self.lex_line(line, state).0
+    }
+
+    // Spans of a line along with the state the next line starts in
+    fn lex_line(&self, line: &str, state: &LexState) -> (Vec<(usize, usize, usize)>, LexState) {
+        let (mut spans, end) = self.tokenize(line, state);
+
+        // Rules picking out a capture group only fill in text no token claimed
+        for rule in &self.rules {
+            if rule.group == 0 {
+                continue;
+            }
+
+            for caps in rule.regex.captures_iter(line) {
+                match caps.get(rule.group) {
+                    Some(m) => {
+                        if m.end() > m.start() && !spans.iter().any(|&(start, end, _)| start < m.end() && m.start() < end) {
+                            spans.push((m.start(), m.end(), rule.scope));
+                        }
+                    },
+                    None => (),
+                }
+            }
+        }
+
+        spans.sort();
+        (spans, end)
+    }
+
+    fn spans_to_scopes(line: &str, spans: &Vec<(usize, usize, usize)>) -> Vec<Option<usize>> {
+        let mut ret = Vec::new();
+        let mut i = 0;
+        for (x, _) in line.grapheme_indices(true) {
+            while i < spans.len() && spans[i].1 <= x {
+                i += 1;
+            }
+
+            if i < spans.len() && spans[i].0 <= x {
+                ret.push(Some(spans[i].2));
+            }
+            else {
+                ret.push(None);
+            }
+        }
+        ret
+    }
+
+    pub fn get_line_scopes(&self, line: &str, state: &LexState) -> Vec<Option<usize>> {
+        Self::spans_to_scopes(line, &self.get_line_spans(line, state))
+    }
+
+    // Scopes of line y, from the cache when update_states has lexed the line as it is
+    pub fn get_scopes(&self, line: &str, y: usize) -> Vec<Option<usize>> {
+        match self.states.get(y) {
+            Some(line_state) if line_state.text == line => Self::spans_to_scopes(line, &line_state.spans),
+            _ => self.get_line_scopes(line, &self.get_line_state(y)),
+        }
+    }
+
+    // Re-lexes the lines edited since the last call, given as the first one and how many at the
+    // end were left alone (see Text::mark_dirty). Only those lines are compared, the ones around
+    // them are reused, so an edit only touches itself and whatever follows it until the lexer
+    // state settles again
+    pub fn update_states(&mut self, text: &Vec<String>, first: usize, kept: usize) {
+        let old_len = self.states.len();
+        let shortest = old_len.min(text.len());
+
+        let mut prefix = first.min(shortest);
+        while prefix < shortest && self.states[prefix].text == text[prefix] {
+            prefix += 1;
+        }
+        let mut suffix = kept.min(shortest - prefix);
+        while suffix < shortest - prefix && self.states[old_len - 1 - suffix].text == text[text.len() - 1 - suffix] {
+            suffix += 1;
+        }
+
+        let changed: Vec<LineState> = text[prefix..text.len() - suffix].iter().map(|line| {
+            LineState { text: line.clone(), start: LexState::default(), end: LexState::default(), spans: Vec::new() }
+        }).collect();
+        self.states.splice(prefix..old_len - suffix, changed);
+
+        let mut state = if prefix > 0 { self.states[prefix - 1].end.clone() } else { LexState::default() };
+        for y in prefix..text.len() {
+            if y >= text.len() - suffix && self.states[y].start == state {
+                break;
+            }
+
+            let (spans, end) = self.lex_line(&text[y], &state);
+            let line_state = &mut self.states[y];
+            line_state.start = state;
+            line_state.end = end.clone();
+            line_state.spans = spans;
+            state = end;
+        }
+    }
+
+    pub fn get_line_state(&self, y: usize) -> LexState {
+        match self.states.get(y) {
+            Some(line_state) => line_state.start.clone(),
+            None => LexState::default(),
+        }
+    }
+
+    pub fn get_line_color(line: &str, y: usize, syntax_handler: &Option<SyntaxHandler>, config: &config::Config) -> Vec<Color> {
+        let mut ret: Vec<Color> = Vec::new();
+
+        match syntax_handler {
+            Some(structs) => {
+                let colors: Vec<Color> = structs.scopes.iter().map(|scope| config.get_scope_color(scope)).collect();
+                let other = config.get_scope_color("other");
+
+                for scope in structs.get_scopes(line, y) {
+                    match scope {
+                        Some(scope) => ret.push(colors[scope]),
+                        None => ret.push(other),
+                    }
                 }
             },
             None => {
-                let mut c_iter = line.graphemes(true);
-                for _ in c_iter {
-                    ret.push(Color{r: 255, g: 255, b: 255, a: 255});
+                let other = config.get_scope_color("other");
+                for _ in line.graphemes(true) {
+                    ret.push(other);
                 }
             }
         }
 
         return ret;
     }
-}
\ No newline at end of file
+
+    pub fn get_line_styles(line: &str, y: usize, syntax_handler: &Option<SyntaxHandler>, config: &config::Config) -> Vec<FontStyle> {
+        match syntax_handler {
+            Some(structs) => {
+                let styles: Vec<FontStyle> = structs.scopes.iter().map(|scope| config.get_scope_style(scope)).collect();
+
+                structs.get_scopes(line, y).into_iter().map(|scope| {
+                    match scope {
+                        Some(scope) => styles[scope],
+                        None => FontStyle::NORMAL,
+                    }
+                }).collect()
+            },
+            None => line.graphemes(true).map(|_| FontStyle::NORMAL).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(lang: &str) -> SyntaxHandler {
+        let path = format!["{}/langs/{}/syntax.json", env!("CARGO_MANIFEST_DIR"), lang];
+        SyntaxHandler::parse_syntax_file(&path).unwrap()
+    }
+
+    // Scope of the first grapheme of `needle` on line `y`, or "" for plain text
+    fn scope_at(handler: &mut SyntaxHandler, text: &[&str], y: usize, needle: &str) -> String {
+        let text: Vec<String> = text.iter().map(|l| l.to_string()).collect();
+        handler.update_states(&text, 0, 0);
+
+        let x = text[y].find(needle).unwrap();
+        let index = text[y][..x].graphemes(true).count();
+        match handler.get_line_scopes(&text[y], &handler.get_line_state(y))[index] {
+            Some(scope) => handler.scopes[scope].clone(),
+            None => "".to_owned(),
+        }
+    }
+
+    fn root_at(handler: &mut SyntaxHandler, text: &[&str], y: usize, needle: &str) -> String {
+        get_scope_root(&scope_at(handler, text, y, needle)).to_owned()
+    }
+
+    #[test]
+    fn rust_strings_and_comments_consume_their_text() {
+        let mut h = load("rs");
+        let text = ["let url = \"http://if.else\"; // fn \"quoted\" /* not a block"];
+
+        assert_eq!(root_at(&mut h, &text, 0, "let"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 0, "http"), "string");
+        assert_eq!(root_at(&mut h, &text, 0, "if."), "string");
+        assert_eq!(root_at(&mut h, &text, 0, "; //"), "");
+        assert_eq!(root_at(&mut h, &text, 0, "fn"), "comment");
+        assert_eq!(root_at(&mut h, &text, 0, "quoted"), "comment");
+        assert_eq!(root_at(&mut h, &text, 0, "/*"), "comment");
+    }
+
+    #[test]
+    fn rust_multi_line_regions() {
+        let mut h = load("rs");
+        let text = ["let a = 1; /* start",
+                    "   fn inside */ let b = r#\"raw",
+                    "\"still raw",
+                    "end\"#; let c = '\"'; let d = \"multi",
+                    "line\"; fn e() {}"];
+
+        assert_eq!(root_at(&mut h, &text, 0, "start"), "comment");
+        assert_eq!(root_at(&mut h, &text, 1, "fn"), "comment");
+        assert_eq!(root_at(&mut h, &text, 1, "let"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 1, "raw"), "string");
+        assert_eq!(root_at(&mut h, &text, 2, "still"), "string");
+        assert_eq!(root_at(&mut h, &text, 3, "end"), "string");
+        assert_eq!(root_at(&mut h, &text, 3, "let c"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 3, "'\"'"), "string");
+        assert_eq!(root_at(&mut h, &text, 3, "let d"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 4, "line"), "string");
+        assert_eq!(root_at(&mut h, &text, 4, "fn"), "keyword");
+    }
+
+    #[test]
+    fn rust_scopes() {
+        let mut h = load("rs");
+        let text = ["#[derive(Debug)]",
+                    "fn get<'a>(x: &'a mut Vec<u32>) -> Option<usize> { println!(\"{}\", 1.5); foo(true) }"];
+
+        assert_eq!(scope_at(&mut h, &text, 0, "#["), "attribute");
+        assert_eq!(scope_at(&mut h, &text, 1, "'a>"), "lifetime");
+        assert_eq!(scope_at(&mut h, &text, 1, "mut"), "keyword.modifier");
+        assert_eq!(scope_at(&mut h, &text, 1, "Vec"), "secondary_word");
+        assert_eq!(scope_at(&mut h, &text, 1, "u32"), "data_type");
+        assert_eq!(scope_at(&mut h, &text, 1, "println"), "macro");
+        assert_eq!(scope_at(&mut h, &text, 1, "1.5"), "constant.numeric");
+        assert_eq!(scope_at(&mut h, &text, 1, "foo"), "function.call");
+        assert_eq!(scope_at(&mut h, &text, 1, "true"), "constant.language");
+    }
+
+    #[test]
+    fn c_tokens() {
+        let mut h = load("c");
+        let text = ["#include <stdio.h>",
+                    "int main() { char c = '\"'; puts(\"if // else\"); /* a",
+                    "return */ return 0; } // while"];
+
+        assert_eq!(root_at(&mut h, &text, 0, "#include"), "preproc");
+        assert_eq!(root_at(&mut h, &text, 1, "int"), "data_type");
+        assert_eq!(root_at(&mut h, &text, 1, "'\"'"), "constant");
+        assert_eq!(root_at(&mut h, &text, 1, "puts"), "");
+        assert_eq!(root_at(&mut h, &text, 1, "if"), "constant");
+        assert_eq!(root_at(&mut h, &text, 1, "// else"), "constant");
+        assert_eq!(root_at(&mut h, &text, 1, "/* a"), "comment");
+        assert_eq!(root_at(&mut h, &text, 2, "return */"), "comment");
+        assert_eq!(root_at(&mut h, &text, 2, "return 0"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 2, "0;"), "constant");
+        assert_eq!(root_at(&mut h, &text, 2, "while"), "comment");
+    }
+
+    #[test]
+    fn cpp_tokens() {
+        let mut h = load("cpp");
+        let text = ["class A { public: const char *s = R\"x(raw",
+                    "\"class\" )x\"; }; // done"];
+
+        assert_eq!(root_at(&mut h, &text, 0, "class"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 0, "public"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 0, "char"), "data_type");
+        assert_eq!(root_at(&mut h, &text, 0, "raw"), "constant");
+        assert_eq!(root_at(&mut h, &text, 1, "class"), "constant");
+        assert_eq!(root_at(&mut h, &text, 1, "; }"), "");
+        assert_eq!(root_at(&mut h, &text, 1, "done"), "comment");
+    }
+
+    #[test]
+    fn json_tokens() {
+        let mut h = load("json");
+        let text = ["{\"key\": \"value: \\\"quoted\\\"\", \"n\": -1.5e3, \"ok\": [true, null]}"];
+
+        assert_eq!(root_at(&mut h, &text, 0, "{"), "preproc");
+        assert_eq!(root_at(&mut h, &text, 0, "\"key\""), "keyword");
+        assert_eq!(root_at(&mut h, &text, 0, "value"), "string");
+        assert_eq!(root_at(&mut h, &text, 0, "quoted"), "string");
+        assert_eq!(root_at(&mut h, &text, 0, "\"n\""), "keyword");
+        assert_eq!(scope_at(&mut h, &text, 0, "-1.5e3"), "constant.numeric");
+        assert_eq!(scope_at(&mut h, &text, 0, "true"), "constant.language");
+        assert_eq!(scope_at(&mut h, &text, 0, "null"), "constant.language");
+    }
+
+    #[test]
+    fn assembly_tokens() {
+        let mut h = load("s");
+        let text = [".global main",
+                    "main: mov r0, #1 @ add r1, r2",
+                    "/* ldr r0,",
+                    "   push */ bx lr"];
+
+        assert_eq!(root_at(&mut h, &text, 0, ".global"), "preproc");
+        assert_eq!(root_at(&mut h, &text, 1, "main:"), "data_type");
+        assert_eq!(root_at(&mut h, &text, 1, "mov"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 1, "r0"), "secondary_word");
+        assert_eq!(root_at(&mut h, &text, 1, "#1"), "constant");
+        assert_eq!(root_at(&mut h, &text, 1, "add"), "comment");
+        assert_eq!(root_at(&mut h, &text, 2, "ldr"), "comment");
+        assert_eq!(root_at(&mut h, &text, 3, "push"), "comment");
+        assert_eq!(root_at(&mut h, &text, 3, "bx"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 3, "lr"), "secondary_word");
+    }
+
+    #[test]
+    fn shell_tokens() {
+        let mut h = load("sh");
+        let text = ["greet() { echo \"hi # $USER\" 'if $x'; } # done",
+                    "if [ $# -gt 1 ]; then echo ${HOME}; fi"];
+
+        assert_eq!(scope_at(&mut h, &text, 0, "greet"), "function");
+        assert_eq!(root_at(&mut h, &text, 0, "hi"), "string");
+        assert_eq!(root_at(&mut h, &text, 0, "$USER"), "string");
+        assert_eq!(root_at(&mut h, &text, 0, "if $x"), "string");
+        assert_eq!(root_at(&mut h, &text, 0, "done"), "comment");
+        assert_eq!(root_at(&mut h, &text, 1, "if"), "keyword");
+        assert_eq!(scope_at(&mut h, &text, 1, "$#"), "keyword.modifier");
+        assert_eq!(scope_at(&mut h, &text, 1, "${HOME}"), "keyword.modifier");
+        assert_eq!(root_at(&mut h, &text, 1, "fi"), "keyword");
+    }
+
+    #[test]
+    fn python_tokens() {
+        let mut h = load("py");
+        let text = ["@property",
+                    "def area(self): return f\"{self.w} # not\" # x",
+                    "doc = \"\"\"for",
+                    "while\"\"\" if True else None"];
+
+        assert_eq!(scope_at(&mut h, &text, 0, "@property"), "attribute");
+        assert_eq!(root_at(&mut h, &text, 1, "def"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 1, "self)"), "secondary_word");
+        assert_eq!(root_at(&mut h, &text, 1, "# not"), "string");
+        assert_eq!(root_at(&mut h, &text, 1, "# x"), "comment");
+        assert_eq!(root_at(&mut h, &text, 2, "for"), "string");
+        assert_eq!(root_at(&mut h, &text, 3, "while"), "string");
+        assert_eq!(root_at(&mut h, &text, 3, "if"), "keyword");
+        assert_eq!(scope_at(&mut h, &text, 3, "True"), "constant.language");
+    }
+
+    #[test]
+    fn makefile_tokens() {
+        let mut h = load("make");
+        let text = ["CC ?= gcc # compiler",
+                    "ifeq ($(CC),gcc)",
+                    "all: main.o",
+                    "\t$(CC) -o $@ $^"];
+
+        assert_eq!(scope_at(&mut h, &text, 0, "CC"), "data_type");
+        assert_eq!(root_at(&mut h, &text, 0, "compiler"), "comment");
+        assert_eq!(root_at(&mut h, &text, 1, "ifeq"), "preproc");
+        assert_eq!(scope_at(&mut h, &text, 2, "all"), "entity.name.function");
+        assert_eq!(scope_at(&mut h, &text, 3, "$(CC)"), "keyword.modifier");
+        assert_eq!(scope_at(&mut h, &text, 3, "$@"), "keyword.modifier");
+    }
+
+    #[test]
+    fn dockerfile_tokens() {
+        let mut h = load("dockerfile");
+        let text = ["# base image",
+                    "FROM rust:1.40 AS build",
+                    "RUN echo \"FROM $HOME\" ${TARGET}"];
+
+        assert_eq!(root_at(&mut h, &text, 0, "base"), "comment");
+        assert_eq!(root_at(&mut h, &text, 1, "FROM"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 1, "AS"), "secondary_word");
+        assert_eq!(root_at(&mut h, &text, 2, "RUN"), "keyword");
+        assert_eq!(root_at(&mut h, &text, 2, "FROM $"), "string");
+        assert_eq!(scope_at(&mut h, &text, 2, "${TARGET}"), "keyword.modifier");
+    }
+
+    #[test]
+    fn incremental_updates_match_full_relex() {
+        let mut h = load("rs");
+        let mut text: Vec<String> = ["fn main() {", "    let a = 1;", "    let b = \"two\";", "    // three", "}"].iter().map(|l| l.to_string()).collect();
+        h.update_states(&text, 0, 0);
+
+        // Each edit with the lines it changed, y1..y2 counted after it
+        let edits: Vec<(Box<dyn Fn(&mut Vec<String>)>, usize, usize)> = vec![
+            (Box::new(|t| t.insert(1, "    /* open".to_owned())), 1, 2),
+            (Box::new(|t| t[3].push_str(" */")), 3, 4),
+            (Box::new(|t| { t.remove(1); }), 1, 1),
+            (Box::new(|t| t[2] = "    let b = \"two".to_owned()), 2, 3),
+            (Box::new(|t| t.push("\"; }".to_owned())), 5, 6),
+            (Box::new(|t| t.truncate(2)), 2, 2),
+        ];
+        for (edit, y1, y2) in edits {
+            edit(&mut text);
+            h.update_states(&text, y1, text.len() - y2);
+
+            let mut fresh = load("rs");
+            fresh.update_states(&text, 0, 0);
+            for (y, line) in text.iter().enumerate() {
+                assert_eq!(h.get_line_state(y), fresh.get_line_state(y));
+                assert_eq!(h.get_scopes(line, y), fresh.get_line_scopes(line, &fresh.get_line_state(y)));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_matches_do_not_stall() {
+        let mut syntax = SyntaxJSON::default();
+        syntax.rules.push(RuleJSON { scope: "empty".to_owned(), pattern: "x*".to_owned(), group: 0 });
+        syntax.rules.push(RuleJSON { scope: "word".to_owned(), pattern: "b".to_owned(), group: 0 });
+        let mut h = SyntaxHandler::from_syntax_json(syntax).unwrap();
+
+        let text = ["aab xx b"];
+        assert_eq!(scope_at(&mut h, &text, 0, "b"), "word");
+        assert_eq!(scope_at(&mut h, &text, 0, "xx"), "empty");
+        assert_eq!(scope_at(&mut h, &text, 0, "a"), "");
+    }
+
+    #[test]
+    fn longer_match_wins_at_the_same_start() {
+        let mut syntax = SyntaxJSON::default();
+        syntax.rules.push(RuleJSON { scope: "keyword".to_owned(), pattern: "in".to_owned(), group: 0 });
+        syntax.rules.push(RuleJSON { scope: "storage.type".to_owned(), pattern: "int".to_owned(), group: 0 });
+        syntax.rules.push(RuleJSON { scope: "constant".to_owned(), pattern: "in".to_owned(), group: 0 });
+        let mut h = SyntaxHandler::from_syntax_json(syntax).unwrap();
+
+        let text = ["int x in y"];
+        assert_eq!(scope_at(&mut h, &text, 0, "int"), "storage.type");
+        assert_eq!(scope_at(&mut h, &text, 0, "t x"), "storage.type");
+        // Equal matches still go to the rule listed first
+        assert_eq!(scope_at(&mut h, &text, 0, "in y"), "keyword");
+    }
+}
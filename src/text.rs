@@ -2,96 +2,215 @@ extern crate sdl2;
 extern crate unicode_segmentation;
 
 use sdl2::pixels::Color;
-use sdl2::render::{Texture, TextureCreator};
-use sdl2::video::WindowContext;
+use sdl2::render::{Canvas, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use sdl2::ttf::FontStyle;
 
 use ::config;
+use ::encoding;
+use ::atlas;
+use ::paths;
 
 use unicode_segmentation::UnicodeSegmentation;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::path::Path;
+
+// Never assigned, so no font has a glyph for it
+const MISSING_CHAR: char = '\u{10FFFF}';
 
 pub struct Text<'ttf, 'a> {
     pub font: sdl2::ttf::Font<'ttf, 'a>,
+    pub fallback_fonts: Vec<sdl2::ttf::Font<'ttf, 'a>>,
     pub font_size: u16,
+    pub tab_width: u32,
 
     pub raw: Vec<String>,
     pub file_path: String,
-
-    pub normal_character_cache: HashMap<String, Texture<'a>>,
-    pub bold_character_cache: HashMap<String, Texture<'a>>,
+    // What the file was opened with, kept when saving unless the settings choose one
+    pub line_ending: encoding::LineEnding,
+    // Lines edited since the syntax handler last caught up, as the first one and how many at the
+    // end were left alone, which stay true however many lines are added or removed in between
+    pub dirty: Option<(usize, usize)>,
+    // Counts edits, for views that only redraw when the buffer changes
+    pub version: u64,
+
+    pub atlas: atlas::GlyphAtlas<'a>,
+    // Font index of every character past the basic multilingual plane met so far, cleared when
+    // the fonts change
+    pub astral_fonts: RefCell<HashMap<String, Option<usize>>>,
+    // Errors met while drawing, for the editor to report after the frame
+    pub errors: Vec<String>,
 
     pub needs_update: bool,
 }
 impl<'ttf, 'a> Text<'ttf, 'a> {
-    pub fn new(font: sdl2::ttf::Font<'ttf, 'a>, raw: Vec<String>, config: &config::Config) -> Text<'ttf, 'a> {
-        Text { font: font, font_size: config.font_size, raw: raw, file_path: "".to_owned(), normal_character_cache: HashMap::new(), bold_character_cache: HashMap::new(), needs_update: true }
+    pub fn new(font: sdl2::ttf::Font<'ttf, 'a>, fallback_fonts: Vec<sdl2::ttf::Font<'ttf, 'a>>, raw: Vec<String>, config: &config::Config) -> Text<'ttf, 'a> {
+        Text { font: font, fallback_fonts: fallback_fonts, font_size: config.font_size, tab_width: config.tab_width, raw: raw, file_path: "".to_owned(), line_ending: encoding::LineEnding::Lf, dirty: Some((0, 0)), version: 0, atlas: atlas::GlyphAtlas::new(), astral_fonts: RefCell::new(HashMap::new()), errors: Vec::new(), needs_update: true }
     }
 
-    pub fn get_bold_char(&mut self, character: &str, texture_creator: &'a TextureCreator<WindowContext>, color: &sdl2::pixels::Color) -> &Texture {
-        if !self.bold_character_cache.contains_key(character) {
-            self.font.set_style(sdl2::ttf::FontStyle::BOLD);
+    // Records an edit that changed lines y1..y2, counted after it. Removed lines are covered by
+    // an empty range where they were
+    pub fn mark_dirty(&mut self, y1: usize, y2: usize) {
+        let kept = self.raw.len().saturating_sub(y2);
+        self.version += 1;
+        self.dirty = match self.dirty {
+            Some((first, old_kept)) => Some((first.min(y1), old_kept.min(kept))),
+            None => Some((y1, kept)),
+        };
+    }
 
-            let surface = self.font.render(character).blended(Color::RGBA(255, 255, 255, 255)).unwrap();
-            let texture = texture_creator.create_texture_from_surface(&surface).unwrap();
+    // For when the whole buffer is replaced
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = Some((0, 0));
+        self.version += 1;
+    }
 
-            self.bold_character_cache.insert(character.to_owned(), texture);
+    // SDL_ttf only knows about the basic multilingual plane, see get_astral_font_index for the rest
+    fn provides(font: &sdl2::ttf::Font, character: &str) -> bool {
+        character.chars().all(|c| (c as u32) <= 0xFFFF && font.find_glyph(c).is_some())
+    }
+
+    // Whether the font draws the character differently from a noncharacter, which comes out as
+    // its missing glyph
+    fn draws(font: &sdl2::ttf::Font, character: &str) -> bool {
+        let render = |text: &str| font.render(text).blended(Color::RGBA(255, 255, 255, 255)).ok()
+                                      .map(|surface| (surface.width(), surface.height(), surface.with_lock(|pixels| pixels.to_vec())));
+        match render(character) {
+            Some(glyph) => Some(glyph) != render(&MISSING_CHAR.to_string()),
+            None => false,
         }
+    }
 
-        {
-            let t = self.bold_character_cache.get_mut(character).unwrap();
-            let (r, g, b) = color.rgb();
-            t.set_color_mod(r, g, b);
+    // Characters past the basic multilingual plane go to the first font that draws them, which
+    // is kept as finding it means drawing them with every font
+    fn get_astral_font_index(&self, character: &str) -> Option<usize> {
+        match self.astral_fonts.borrow().get(character) {
+            Some(&index) => return index,
+            None => (),
         }
 
-        self.bold_character_cache.get(character).unwrap()
+        let index = if Self::draws(&self.font, character) {
+            None
+        }
+        else {
+            self.fallback_fonts.iter().position(|font| Self::draws(font, character))
+        };
+        self.astral_fonts.borrow_mut().insert(character.to_owned(), index);
+        index
     }
 
-    pub fn get_normal_char(&mut self, character: &str, texture_creator: &'a TextureCreator<WindowContext>, color: &sdl2::pixels::Color) -> &Texture {
-        if !self.normal_character_cache.contains_key(character) {
-            self.font.set_style(sdl2::ttf::FontStyle::NORMAL);
+    // Index into fallback_fonts of the font to draw the character with, None for the main font
+    fn get_font_index(&self, character: &str) -> Option<usize> {
+        if character.chars().any(|c| (c as u32) > 0xFFFF) {
+            return self.get_astral_font_index(character);
+        }
+        if Self::provides(&self.font, character) {
+            return None;
+        }
+        self.fallback_fonts.iter().position(|font| Self::provides(font, character))
+    }
 
-            let surface = self.font.render(character).blended(Color::RGBA(255, 255, 255, 255)).unwrap();
-            let texture = texture_creator.create_texture_from_surface(&surface).unwrap();
+    fn get_glyph(&mut self, character: &str, style: FontStyle, texture_creator: &'a TextureCreator<WindowContext>) -> atlas::Glyph {
+        match self.atlas.get(style.bits(), character) {
+            Some(glyph) => return glyph,
+            None => (),
+        }
 
-            self.normal_character_cache.insert(character.to_owned(), texture);
+        let font = match self.get_font_index(character) {
+            Some(i) => &mut self.fallback_fonts[i],
+            None => &mut self.font,
+        };
+        font.set_style(style);
+        let surface = font.render(character).blended(Color::RGBA(255, 255, 255, 255)).ok();
+        // Widths are always measured unstyled
+        font.set_style(FontStyle::NORMAL);
+
+        let (width, height) = surface.as_ref().map_or((0, 0), |surface| (surface.width(), surface.height()));
+        match self.atlas.insert(style.bits(), character, surface, texture_creator) {
+            Ok(glyph) => glyph,
+            // Left as a gap and tried again the next time it is drawn
+            Err(e) => {
+                self.errors.push(e);
+                atlas::Glyph::blank(width, height)
+            }
         }
+    }
+
+    // Width the character advances the line by, whatever style it is drawn in
+    pub fn get_char_width(&mut self, character: &str, texture_creator: &'a TextureCreator<WindowContext>) -> u32 {
+        self.get_glyph(character, FontStyle::NORMAL, texture_creator).width
+    }
+
+    // Adds a character to the batch and returns its advance; styled glyphs advance like
+    // unstyled ones so cursor and selection positions still line up
+    pub fn queue_char(&mut self, batch: &mut Vec<atlas::Quad>, character: &str, style: FontStyle, color: Color, x: i32, y: i32, texture_creator: &'a TextureCreator<WindowContext>) -> u32 {
+        let advance = self.get_char_width(character, texture_creator);
+        let glyph = self.get_glyph(character, style, texture_creator);
+        atlas::GlyphAtlas::queue(batch, glyph, x, y, color);
+        advance
+    }
+
+    pub fn draw_batch(&mut self, canvas: &mut Canvas<Window>, batch: &mut Vec<atlas::Quad>) {
+        self.atlas.draw(canvas, batch);
+    }
 
-        {
-            let t = self.normal_character_cache.get_mut(character).unwrap();
-            let (r, g, b) = color.rgb();
-            t.set_color_mod(r, g, b);
+    // Draws a string in one style and color at once, returning its width
+    pub fn draw_text(&mut self, canvas: &mut Canvas<Window>, text: &str, style: FontStyle, color: Color, x: i32, y: i32, texture_creator: &'a TextureCreator<WindowContext>) -> u32 {
+        let mut batch = Vec::new();
+        let mut width = 0;
+        for character in text.graphemes(true) {
+            width += self.queue_char(&mut batch, character, style, color, x + width as i32, y, texture_creator);
         }
+        self.draw_batch(canvas, &mut batch);
+        width
+    }
 
-        self.normal_character_cache.get(character).unwrap()
+    pub fn get_tab_stop(&self, x: u32) -> u32 {
+        let (space_w, _) = self.font.size_of(" ").unwrap();
+        let stop = space_w * self.tab_width;
+        if stop == 0 {
+            return x;
+        }
+        (x/stop + 1)*stop
     }
 
-    pub fn get_text_type(&self) -> String {
-        let n_iter = self.file_path.graphemes(true).rev();
-        let mut ext = "".to_owned();
-        for n in n_iter {
-            if n == "." {
-                return ext;
+    pub fn get_text_width(&self, line: &str) -> u32 {
+        let mut width = 0;
+        for (i, chunk) in line.split('\t').enumerate() {
+            if i > 0 {
+                width = self.get_tab_stop(width);
             }
-            else if n == "/" {
-                break;
+            if Self::provides(&self.font, chunk) {
+                let (w, _) = self.font.size_of(chunk).unwrap();
+                width += w;
             }
             else {
-                ext.insert_str(0, n);
+                // Measured glyph by glyph, each with the font it's drawn with
+                for character in chunk.graphemes(true) {
+                    let font = match self.get_font_index(character) {
+                        Some(i) => &self.fallback_fonts[i],
+                        None => &self.font,
+                    };
+                    let (w, _) = font.size_of(character).unwrap();
+                    width += w;
+                }
             }
         }
-        return "?".to_owned();
+        width
     }
 
+    // Absolute directory of the file, "~" while there is none
     pub fn get_text_dir(&self) -> String {
-        let mut n_iter = self.file_path.graphemes(true).rev();
-        let mut n = n_iter.next();
-        while n != None {
-            if n.unwrap() == "/" {
-                return n_iter.rev().collect();
-            }
-            n = n_iter.next();
+        if self.file_path == "" {
+            return "~".to_owned();
+        }
+
+        let file_path = paths::make_absolute(&self.file_path);
+        match Path::new(&file_path).parent() {
+            Some(dir) => dir.display().to_string(),
+            None => file_path,
         }
-        return "~".to_owned();
     }
 }
@@ -0,0 +1,260 @@
+extern crate serde;
+extern crate serde_json;
+extern crate serde_yaml;
+
+use ::utils;
+use ::paths;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Extensions tried, in order, when looking a theme up by name
+const THEME_EXTENSIONS: [&str; 3] = ["json", "yaml", "yml"];
+
+// A scope is either just a color or a color with font styles
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScopeStyleJSON {
+    Color([u8; 3]),
+    Styled {
+        color: [u8; 3],
+        #[serde(default)]
+        bold: bool,
+        #[serde(default)]
+        italic: bool,
+        #[serde(default)]
+        underline: bool,
+        #[serde(default)]
+        strikethrough: bool,
+    },
+}
+
+// Keys a theme leaves out keep the default theme's colors
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThemeJSON {
+    pub syntax_colors: HashMap<String, ScopeStyleJSON>,
+
+    pub bg_color: [u8; 3],
+    pub line_number_color: [u8; 3],
+
+    pub bar_color: [u8; 3],
+    pub bar_text_color: [u8; 3],
+
+    pub select_color: [u8; 3],
+    pub search_color: [u8; 3],
+    pub match_color: [u8; 3],
+
+    pub bracket_colors: Vec<[u8; 3]>,
+    pub bracket_error_color: [u8; 3],
+}
+// The same colors as themes/default.json
+impl Default for ThemeJSON {
+    fn default() -> ThemeJSON {
+        let mut syntax_colors = HashMap::new();
+        for &(scope, color) in [("other", [204, 204, 204]), ("constant", [153, 204, 153]), ("secondary_word", [102, 153, 204]),
+                                ("preproc", [242, 119, 122]), ("data_type", [249, 145, 87]), ("string", [153, 204, 153]),
+                                ("attribute", [242, 119, 122]), ("macro", [242, 119, 122]), ("lifetime", [242, 119, 122]),
+                                ("keyword.modifier", [242, 119, 122]), ("function", [102, 204, 204]),
+                                ("entity.name.function", [102, 204, 204]), ("entity.name.type", [249, 145, 87]),
+                                ("storage", [249, 145, 87]), ("support", [102, 153, 204])].iter() {
+            syntax_colors.insert(scope.to_owned(), ScopeStyleJSON::Color(color));
+        }
+        syntax_colors.insert("keyword".to_owned(), ScopeStyleJSON::Styled { color: [204, 153, 204], bold: true, italic: false, underline: false, strikethrough: false });
+        syntax_colors.insert("comment".to_owned(), ScopeStyleJSON::Styled { color: [103, 103, 103], bold: false, italic: true, underline: false, strikethrough: false });
+
+        ThemeJSON {
+            syntax_colors: syntax_colors,
+
+            bg_color: [45, 45, 45],
+            line_number_color: [255, 255, 255],
+
+            bar_color: [0, 0, 0],
+            bar_text_color: [255, 255, 255],
+
+            select_color: [151, 151, 151],
+            search_color: [255, 204, 102],
+            match_color: [102, 204, 255],
+
+            bracket_colors: vec![[255, 215, 0], [218, 112, 214], [23, 159, 255]],
+            bracket_error_color: [242, 119, 122],
+        }
+    }
+}
+
+// Colors a config sets on top of its theme. The syntax_*_color keys are the ones configs used
+// before themes, each setting the color of one scope
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThemeOverridesJSON {
+    pub syntax_colors: HashMap<String, ScopeStyleJSON>,
+    pub syntax_constant_color: Option<[u8; 3]>,
+    pub syntax_keyword_color: Option<[u8; 3]>,
+    pub syntax_secondary_word_color: Option<[u8; 3]>,
+    pub syntax_preproc_color: Option<[u8; 3]>,
+    pub syntax_data_type_color: Option<[u8; 3]>,
+    pub syntax_comment_color: Option<[u8; 3]>,
+    pub syntax_other_color: Option<[u8; 3]>,
+
+    pub bg_color: Option<[u8; 3]>,
+    pub line_number_color: Option<[u8; 3]>,
+
+    pub bar_color: Option<[u8; 3]>,
+    pub bar_text_color: Option<[u8; 3]>,
+
+    pub select_color: Option<[u8; 3]>,
+    pub search_color: Option<[u8; 3]>,
+    pub match_color: Option<[u8; 3]>,
+
+    pub bracket_colors: Option<Vec<[u8; 3]>>,
+    pub bracket_error_color: Option<[u8; 3]>,
+}
+impl ThemeOverridesJSON {
+    pub fn apply(&self, theme: &mut ThemeJSON) {
+        // A legacy key only changes the color, the theme's style for the scope stays
+        for &(scope, color) in [("constant", self.syntax_constant_color), ("keyword", self.syntax_keyword_color),
+                                ("secondary_word", self.syntax_secondary_word_color), ("preproc", self.syntax_preproc_color),
+                                ("data_type", self.syntax_data_type_color), ("comment", self.syntax_comment_color),
+                                ("other", self.syntax_other_color)].iter() {
+            match color {
+                Some(c) => {
+                    let entry = theme.syntax_colors.entry(scope.to_owned()).or_insert(ScopeStyleJSON::Color(c));
+                    match entry {
+                        ScopeStyleJSON::Color(color) => *color = c,
+                        ScopeStyleJSON::Styled { color, .. } => *color = c,
+                    }
+                },
+                None => (),
+            }
+        }
+        theme.syntax_colors.extend(self.syntax_colors.iter().map(|(scope, style)| (scope.clone(), style.clone())));
+
+        let mut colors = [(&mut theme.bg_color, self.bg_color), (&mut theme.line_number_color, self.line_number_color),
+                      (&mut theme.bar_color, self.bar_color), (&mut theme.bar_text_color, self.bar_text_color),
+                      (&mut theme.select_color, self.select_color), (&mut theme.search_color, self.search_color),
+                      (&mut theme.match_color, self.match_color), (&mut theme.bracket_error_color, self.bracket_error_color)];
+        for (color, value) in colors.iter_mut() {
+            match *value {
+                Some(value) => **color = value,
+                None => (),
+            }
+        }
+        match self.bracket_colors {
+            Some(ref colors) => theme.bracket_colors = colors.clone(),
+            None => (),
+        }
+    }
+}
+
+// A theme in a more specific data dir hides one with the same name further down
+pub fn find_theme_file(name: &str) -> Option<String> {
+    for dir in paths::get_data_subdirs("themes") {
+        for ext in THEME_EXTENSIONS.iter() {
+            let path = format!["{}/{}.{}", dir, name, ext];
+            if Path::new(&path).is_file() {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+pub fn get_theme_names() -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for dir in paths::get_data_subdirs("themes") {
+        match fs::read_dir(dir) {
+            Ok(entries) => names.extend(entries.filter_map(|e| e.ok())
+                                               .map(|e| e.path())
+                                               .filter(|p| p.extension().map_or(false, |ext| THEME_EXTENSIONS.iter().any(|&e| ext == e)))
+                                               .filter_map(|p| p.file_stem().map(|stem| stem.to_string_lossy().into_owned()))),
+            Err(_) => (),
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+pub fn load_theme(name: &str) -> Result<ThemeJSON, String> {
+    match find_theme_file(name) {
+        Some(path) => parse_theme_file(&path),
+        None => Err(format!["No theme named {} in {}", name, paths::get_data_subdirs("themes").join(", ")]),
+    }
+}
+
+// .json files are in the editor's own format, .yaml/.yml ones are base16 schemes
+pub fn parse_theme_file(path: &str) -> Result<ThemeJSON, String> {
+    let file = utils::read_file(path);
+
+    if path.ends_with(".json") {
+        serde_json::from_str(&file).map_err(|e| format!["{}: {}", path, e])
+    }
+    else {
+        let decoded: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(&file).map_err(|e| format!["{}: {}", path, e])?;
+
+        // Unquoted colors made only of digits come out of the YAML parser as numbers
+        let mut scheme = HashMap::new();
+        for (key, value) in decoded {
+            match value {
+                serde_yaml::Value::String(s) => { scheme.insert(key, s); },
+                serde_yaml::Value::Number(n) => { scheme.insert(key, format!["{:0>6}", n.to_string()]); },
+                _ => (),
+            }
+        }
+        import_base16(&scheme).map_err(|e| format!["{}: {}", path, e])
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let mut ret = [0; 3];
+    for i in 0..3 {
+        ret[i] = u8::from_str_radix(hex.get(i*2..i*2 + 2)?, 16).ok()?;
+    }
+    Some(ret)
+}
+
+// base16 schemes name 16 colors base00..base0F with fixed roles: 00-07 go from the background
+// to the brightest foreground, 08-0F are the accents (variables, constants, types, strings,
+// support, functions, keywords and deprecated)
+pub fn import_base16(scheme: &HashMap<String, String>) -> Result<ThemeJSON, String> {
+    let mut base = Vec::new();
+    for i in 0..16 {
+        let key = format!["base{:02X}", i];
+        match scheme.get(&key).and_then(|hex| parse_hex(hex)) {
+            Some(color) => base.push(color),
+            None => return Err(format!["{} is missing or not a hex color", key]),
+        }
+    }
+
+    let mut syntax_colors = HashMap::new();
+    for &(scope, i) in [("other", 0x05), ("comment", 0x03), ("constant", 0x09), ("string", 0x0B),
+                        ("keyword", 0x0E), ("keyword.modifier", 0x0C), ("storage", 0x0E),
+                        ("secondary_word", 0x0D), ("preproc", 0x08), ("data_type", 0x0A),
+                        ("entity.name.type", 0x0A), ("function", 0x0D), ("entity.name.function", 0x0D),
+                        ("attribute", 0x08), ("macro", 0x0C), ("lifetime", 0x08), ("support", 0x0C)].iter() {
+        syntax_colors.insert(scope.to_owned(), ScopeStyleJSON::Color(base[i]));
+    }
+
+    Ok(ThemeJSON {
+        syntax_colors: syntax_colors,
+
+        bg_color: base[0x00],
+        line_number_color: base[0x04],
+
+        bar_color: base[0x01],
+        bar_text_color: base[0x05],
+
+        select_color: base[0x04],
+        search_color: base[0x0A],
+        match_color: base[0x0D],
+
+        bracket_colors: vec![base[0x0A], base[0x0E], base[0x0D]],
+        bracket_error_color: base[0x08],
+    })
+}
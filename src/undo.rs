@@ -52,6 +52,7 @@ impl UndoHandler {
             cursor.screen_y = state.cursor_screen_y;
             cursor.number_w = state.cursor_number_w;
             text.raw = state.text.clone();
+            text.mark_all_dirty();
         }
     }
 
@@ -66,6 +67,7 @@ impl UndoHandler {
             cursor.screen_y = state.cursor_screen_y;
             cursor.number_w = state.cursor_number_w;
             text.raw = state.text.clone();
+            text.mark_all_dirty();
         }
     }
 }
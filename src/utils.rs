@@ -1,9 +1,24 @@
 use std::io::prelude::*;
 use std::fs::File;
 use std::env;
+use std::path::Path;
+use std::fs;
 
-use ::text;
 use ::editor;
+use ::syntax;
+use ::indent;
+use ::pairs;
+use ::grammar;
+use ::detect;
+use ::theme;
+use ::watch;
+use ::config;
+use ::paths;
+use ::editorconfig;
+use ::encoding;
+
+// Files a langs/<id>/ directory may hold besides a grammar
+const LANGUAGE_FILES: [&str; 5] = ["name", "syntax.json", "indent.json", "pairs.json", "detect.json"];
 
 pub fn read_file(path: &str) -> String {
     let file = File::open(path);
@@ -20,23 +35,92 @@ pub fn read_file(path: &str) -> String {
     }
 }
 
-pub fn save_file(path: &str, buffer: &Vec<String>) {
-    let mut file = File::create(path).unwrap();
-    let mut s = String::new();
+// Opens a file into the editor, decoding it with the charset its settings ask for. Returns
+// false, leaving the buffer as it was, if it can't be read
+pub fn load_buffer(editor: &mut editor::Editor, path: &str, config: &config::Config) -> bool {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            report_errors(editor, &vec![format!["{}: {}", path, e]]);
+            return false;
+        }
+    };
+
+    let contents = encoding::Charset::Utf8.decode(&bytes);
+    editor.text.line_ending = encoding::LineEnding::detect(&contents);
+    editor.text.raw = encoding::split_lines(&contents, editor.text.line_ending);
+    editor.text.file_path = path.to_owned();
+
+    editor.language_locked = false;
+    load_language(editor, config);
 
-    for l in buffer.iter() {
-        s.push_str(l);
-        s.push_str("\n");
+    // The charset is only known once the settings are
+    match editor.settings.charset {
+        Some(charset) if charset != encoding::Charset::Utf8 && charset != encoding::Charset::Utf8Bom => {
+            let contents = charset.decode(&bytes);
+            editor.text.line_ending = encoding::LineEnding::detect(&contents);
+            editor.text.raw = encoding::split_lines(&contents, editor.text.line_ending);
+            editor.text.mark_all_dirty();
+        },
+        _ => (),
     }
-    s.pop();
+    true
+}
+
+// Writes the buffer with the line endings, final newline and charset of its settings
+pub fn save_file(editor: &mut editor::Editor, path: &str) {
+    trim_trailing_whitespace(editor);
 
-    let result = file.write(&s.into_bytes());
+    let line_ending = editor.settings.end_of_line.unwrap_or(editor.text.line_ending);
+    let mut s = editor.text.raw.join(line_ending.as_str());
+
+    let ends_with_newline = editor.text.raw.len() > 1 && editor.text.raw.last().map_or(false, |l| l == "");
+    match editor.settings.insert_final_newline {
+        Some(true) if !ends_with_newline && s != "" => s.push_str(line_ending.as_str()),
+        Some(false) if ends_with_newline => {
+            let len = s.len() - line_ending.as_str().len();
+            s.truncate(len);
+        },
+        _ => (),
+    }
+
+    let charset = editor.settings.charset.unwrap_or(encoding::Charset::Utf8);
+    let result = File::create(path).and_then(|mut file| file.write_all(&charset.encode(&s)));
     match result {
         Ok(_) => {},
-        Err(e) => println!["{}", e],
+        Err(e) => report_errors(editor, &vec![format!["{}: {}", path, e]]),
     }
 }
 
+// Run before saving, when the language's settings ask for it
+fn trim_trailing_whitespace(editor: &mut editor::Editor) {
+    if !editor.settings.trim_trailing_whitespace {
+        return;
+    }
+
+    if !editor.text.raw.iter().any(|line| line.trim_end().len() < line.len()) {
+        return;
+    }
+
+    // Snapshots on both sides so the trim can be undone on its own
+    update_timer(editor);
+    for line in editor.text.raw.iter_mut() {
+        let len = line.trim_end().len();
+        line.truncate(len);
+    }
+    let len = editor.text.raw.len();
+    editor.text.mark_dirty(0, len);
+
+    let len = editor.text.raw[editor.cursor.get_absolute_y()].len() as u32;
+    if editor.cursor.x > len {
+        editor.cursor.x = len;
+        editor.cursor.wanted_x = len;
+    }
+    editor.selected.reset_selection();
+    editor.text.needs_update = true;
+    update_timer(editor);
+}
+
 pub fn number_of_digits(n: usize) -> usize {
     let mut i = 0;
     let mut n = n;
@@ -47,15 +131,58 @@ pub fn number_of_digits(n: usize) -> usize {
     i
 }
 
-pub fn get_lang_name(text: &text::Text) -> String {
-    let text_type = text.get_text_type();
-    if text_type == "?" {
-        return text_type;
+// Every language as (id, directory), sorted by id; a language in a more specific data dir
+// hides one with the same id further down
+pub fn get_language_dirs() -> Vec<(String, String)> {
+    let mut languages: Vec<(String, String)> = Vec::new();
+    for langs_dir in paths::get_data_subdirs("langs") {
+        match fs::read_dir(&langs_dir) {
+            Ok(entries) => {
+                for entry in entries.filter_map(|e| e.ok()).filter(|e| e.path().is_dir()) {
+                    let id = entry.file_name().to_string_lossy().into_owned();
+                    if !languages.iter().any(|(other, _)| *other == id) {
+                        languages.push((id, entry.path().display().to_string()));
+                    }
+                }
+            },
+            Err(_) => (),
+        }
+    }
+    languages.sort();
+    languages
+}
+
+pub fn get_language_dir(id: &str) -> Option<String> {
+    get_language_dirs().into_iter().find(|(other, _)| other == id).map(|(_, dir)| dir)
+}
+
+pub fn is_language_path(path: &str) -> bool {
+    paths::get_data_subdirs("langs").iter().any(|dir| path.starts_with(dir))
+}
+
+pub fn get_lang_name(language: &Option<String>) -> String {
+    match language.as_ref().and_then(|language| get_language_dir(language)) {
+        Some(dir) => {
+            let path = format!["{}/name", dir];
+            return read_file(&path).trim().to_owned();
+        },
+        None => return "?".to_owned(),
     }
-    else {
-        let path = format!["{}/langs/{}/name", env::current_dir().unwrap().display(), text_type];
-        return read_file(&path).trim().to_owned();
+}
+
+// Catches the syntax and bracket handlers up with the edits made since they last did
+pub fn update_syntax(editor: &mut editor::Editor) {
+    match editor.text.dirty.take() {
+        Some((first, kept)) => {
+            match &mut editor.syntax_handler {
+                Some(syntax_handler) => syntax_handler.update_states(&editor.text.raw, first, kept),
+                None => (),
+            }
+            editor.bracket_handler.invalidate(first);
+        },
+        None => (),
     }
+    editor.bracket_handler.update(&editor.text.raw, &editor.syntax_handler);
 }
 
 pub fn update_timer(editor: &mut editor::Editor) {
@@ -67,3 +194,205 @@ pub fn update_timer(editor: &mut editor::Editor) {
 
     editor.char_timer = 0;
 }
+
+// Parses an optional file of a language directory, Ok(None) meaning it isn't there
+pub fn load_file<T>(path: &str, parse: fn(&str) -> Result<T, Vec<String>>) -> Result<Option<T>, Vec<String>> {
+    if !Path::new(path).is_file() {
+        return Ok(None);
+    }
+
+    match parse(path) {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(errors) => Err(errors.iter().map(|e| format!["{}: {}", path, e]).collect()),
+    }
+}
+
+// syntax.json, or the grammar file next to it when there is none. Rules the importer skipped
+// go in warnings
+pub fn load_syntax(dir: &str, warnings: &mut Vec<String>) -> Result<Option<syntax::SyntaxHandler>, Vec<String>> {
+    let path = format!["{}/syntax.json", dir];
+    match grammar::find_grammar_file(dir) {
+        Some(grammar_path) if !Path::new(&path).is_file() => {
+            match grammar::import_grammar(&grammar_path) {
+                Ok(import) => {
+                    warnings.extend(import.warnings.iter().map(|warning| format!["{}: skipped {}", grammar_path, warning]));
+                    match syntax::SyntaxHandler::from_syntax_json(import.syntax) {
+                        Ok(syntax_handler) => Ok(Some(syntax_handler)),
+                        Err(errors) => Err(errors.iter().map(|e| format!["{}: {}", grammar_path, e]).collect()),
+                    }
+                },
+                Err(e) => Err(vec![format!["{}: {}", grammar_path, e]]),
+            }
+        },
+        _ => load_file(&path, syntax::SyntaxHandler::parse_syntax_file),
+    }
+}
+
+// One line for the status bar: regex errors span several lines, the first and last say enough
+pub fn summarize_error(error: &str) -> String {
+    let cwd = format!["{}/", env::current_dir().unwrap().display()];
+    let lines: Vec<&str> = error.lines().collect();
+    let summary = match (lines.first(), lines.last()) {
+        (Some(first), Some(last)) if lines.len() > 1 => format!["{} {}", first, last.trim().trim_start_matches("error: ")],
+        _ => error.to_owned(),
+    };
+    summary.replacen(&cwd, "", 1)
+}
+
+// Keeps every error for the messages prompt and shows the first one in the status bar. An
+// error repeated every frame is only kept once
+pub fn report_errors(editor: &mut editor::Editor, errors: &Vec<String>) {
+    for e in errors {
+        if editor.messages.last() != Some(e) {
+            editor.messages.push(e.clone());
+        }
+    }
+    match errors.first() {
+        Some(error) => {
+            let more = if errors.len() > 1 { format![" (+{} more)", errors.len() - 1] } else { "".to_owned() };
+            editor.status_message = Some(format!["{}{}", summarize_error(error), more]);
+        },
+        None => (),
+    }
+}
+
+// Returns whether every file of the language loaded without errors
+pub fn load_language(editor: &mut editor::Editor, config: &config::Config) -> bool {
+    if !editor.language_locked {
+        let mut warnings = Vec::new();
+        editor.language = detect::detect_language(&editor.text.file_path, &editor.text.raw, &mut warnings);
+        report_errors(editor, &warnings);
+    }
+
+    editor.language_name = get_lang_name(&editor.language);
+
+    // A new syntax handler has lexed nothing yet
+    editor.text.mark_all_dirty();
+
+    match editor.language.as_ref().and_then(|language| get_language_dir(language)) {
+        Some(dir) => {
+            let mut errors = Vec::new();
+
+            let mut warnings = Vec::new();
+            editor.syntax_handler = match load_syntax(&dir, &mut warnings) {
+                Ok(syntax_handler) => syntax_handler,
+                Err(e) => {
+                    errors.extend(e);
+                    None
+                }
+            };
+
+            editor.indent_handler = match load_file(&format!["{}/indent.json", dir], indent::IndentHandler::parse_indent_file) {
+                Ok(indent_handler) => indent_handler,
+                Err(e) => {
+                    errors.extend(e);
+                    None
+                }
+            };
+
+            editor.pair_handler = match load_file(&format!["{}/pairs.json", dir], pairs::PairHandler::parse_pairs_file) {
+                Ok(pair_handler) => pair_handler,
+                Err(e) => {
+                    errors.extend(e);
+                    None
+                }
+            };
+
+            errors.extend(apply_language_settings(editor, config));
+
+            report_errors(editor, &warnings);
+            report_errors(editor, &errors);
+            errors.len() == 0
+        },
+        None => {
+            editor.syntax_handler = None;
+            editor.indent_handler = None;
+            editor.pair_handler = None;
+
+            let errors = apply_language_settings(editor, config);
+            report_errors(editor, &errors);
+            errors.len() == 0
+        },
+    }
+}
+
+// Picks the config's settings for the current language, then the buffer's .editorconfig
+// properties; the settings' pairs replace pairs.json
+fn apply_language_settings(editor: &mut editor::Editor, config: &config::Config) -> Vec<String> {
+    editor.settings = config.get_language_settings(&editor.language);
+
+    let warnings = editorconfig::apply_editorconfig(&mut editor.settings, &editor.text.file_path, &editor.text.get_text_dir());
+    report_errors(editor, &warnings);
+    match editor.word_wrap {
+        Some(word_wrap) => editor.settings.word_wrap = word_wrap,
+        None => (),
+    }
+    // Indenting measures tabs from the settings, the same width they are drawn with
+    if editor.settings.tab_width.is_none() {
+        editor.settings.tab_width = Some(config.tab_width);
+    }
+    editor.text.tab_width = editor.settings.tab_width.unwrap_or(config.tab_width);
+
+    match editor.settings.pairs {
+        Some(ref pairs) => {
+            match pairs::PairHandler::from_pairs(pairs) {
+                Ok(pair_handler) => editor.pair_handler = Some(pair_handler),
+                Err(errors) => {
+                    let language = editor.language_name.clone();
+                    return errors.iter().map(|e| format!["{} settings: {}", language, e]).collect();
+                }
+            }
+        },
+        None => (),
+    }
+    Vec::new()
+}
+
+// Loads the language files again after they changed on disk, keeping what was loaded before
+// if the new files have errors
+pub fn reload_language(editor: &mut editor::Editor, config: &config::Config) {
+    let language = editor.language.clone();
+    let language_name = editor.language_name.clone();
+    let syntax_handler = editor.syntax_handler.take();
+    let indent_handler = editor.indent_handler.take();
+    let pair_handler = editor.pair_handler.take();
+
+    if !load_language(editor, config) {
+        editor.language = language;
+        editor.language_name = language_name;
+        editor.syntax_handler = syntax_handler;
+        editor.indent_handler = indent_handler;
+        editor.pair_handler = pair_handler;
+    }
+}
+
+// Files whose changes are applied while the editor runs: every config layer, its theme, every
+// language definition and the .editorconfig files of the open file
+pub fn watch_config_files(watcher: &mut watch::FileWatcher, config_layers: &Vec<String>, config: &config::Config, file_path: &str) {
+    watcher.clear();
+    for path in config_layers {
+        watcher.watch(path);
+    }
+
+    match theme::find_theme_file(&config.theme) {
+        Some(path) => watcher.watch(&path),
+        None => (),
+    }
+
+    for (_, dir) in get_language_dirs() {
+        for name in LANGUAGE_FILES.iter().chain(grammar::GRAMMAR_FILES.iter()) {
+            watcher.watch(&format!["{}/{}", dir, name]);
+        }
+    }
+
+    for path in editorconfig::get_editorconfig_paths(file_path) {
+        watcher.watch(&path);
+    }
+}
+
+// Overrides detection for the current buffer; None means plain text
+pub fn set_language(editor: &mut editor::Editor, language: Option<String>, config: &config::Config) {
+    editor.language = language;
+    editor.language_locked = true;
+    load_language(editor, config);
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::SystemTime;
+
+// Notices edits to files by polling their modification times
+pub struct FileWatcher {
+    files: HashMap<String, Option<SystemTime>>,
+}
+impl FileWatcher {
+    pub fn new() -> FileWatcher {
+        FileWatcher { files: HashMap::new() }
+    }
+
+    fn modified(path: &str) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    pub fn watch(&mut self, path: &str) {
+        if !self.files.contains_key(path) {
+            self.files.insert(path.to_owned(), Self::modified(path));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+
+    // Files written, created or removed since the last call
+    pub fn changed(&mut self) -> Vec<String> {
+        let mut ret = Vec::new();
+        for (path, time) in self.files.iter_mut() {
+            let modified = Self::modified(path);
+            if modified != *time {
+                *time = modified;
+                ret.push(path.clone());
+            }
+        }
+        ret
+    }
+}